@@ -0,0 +1,47 @@
+// 后台循环出错时重试前的等待时间, 连续出错时加倍.
+// 例如fd用完(EMFILE)时accept/recv会立即再次失败, 不等待会占满cpu和日志
+use std::time::Duration;
+
+const MIN_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_secs(5);
+
+pub struct Backoff {
+    delay: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff { delay: MIN_BACKOFF }
+    }
+}
+
+impl Backoff {
+    // 成功后恢复到最短的等待时间
+    pub fn reset(&mut self) {
+        self.delay = MIN_BACKOFF;
+    }
+
+    // 这一次重试前等待的时间
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.delay;
+        self.delay = (delay * 2).min(MAX_BACKOFF);
+        delay
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn doubles_up_to_max() {
+        let mut backoff = Backoff::default();
+        let delays = (0..8).map(|_| backoff.next_delay()).collect::<Vec<_>>();
+        assert_eq!(delays[0], MIN_BACKOFF);
+        assert_eq!(delays[1], MIN_BACKOFF * 2);
+        assert_eq!(delays[7], MAX_BACKOFF);
+
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), MIN_BACKOFF);
+    }
+}
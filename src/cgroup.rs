@@ -0,0 +1,398 @@
+// cgroup v2
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+
+use heim::process::Pid;
+use tokio::sync::OnceCell;
+
+use crate::data::{CgroupStat, DeviceIo, Pressure, PressureLine};
+
+// cgroup v2的挂载点. 混合模式下通常挂载在/sys/fs/cgroup/unified.
+// 记录期间不会变化, 只读取一次/proc/self/mountinfo
+pub async fn mount_point() -> &'static Path {
+    static MOUNT_POINT: OnceCell<PathBuf> = OnceCell::const_new();
+
+    MOUNT_POINT
+        .get_or_init(|| async {
+            let mount_info = tokio::fs::read_to_string("/proc/self/mountinfo")
+                .await
+                .unwrap_or_default();
+            mount_info
+                .lines()
+                .find_map(|line| {
+                    let (mount, fs) = line.split_once(" - ")?;
+                    if fs.split_whitespace().next()? == "cgroup2" {
+                        mount.split_whitespace().nth(4).map(PathBuf::from)
+                    } else {
+                        None
+                    }
+                })
+                .unwrap_or_else(|| PathBuf::from("/sys/fs/cgroup"))
+        })
+        .await
+}
+
+// 进程所在的cgroup v2目录, `root`为挂载点
+pub async fn process_cgroup(root: &Path, pid: Pid) -> anyhow::Result<PathBuf> {
+    let cgroup = tokio::fs::read_to_string(format!("/proc/{}/cgroup", pid)).await?;
+    // v2的条目格式为`0::<path>`
+    let path = cgroup
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .ok_or_else(|| anyhow::anyhow!("process {} is not in a cgroup v2 hierarchy", pid))?;
+    Ok(root.join(path.trim().trim_start_matches('/')))
+}
+
+// `max`为不限制
+pub fn parse_max(value: &str) -> Option<u64> {
+    match value.trim() {
+        "max" => None,
+        value => value.parse().ok(),
+    }
+}
+
+// 从`dir`向上直到`root`的全部cgroup中某个文件的最小值, 即实际生效的限制
+async fn effective_limit<F>(dir: &Path, root: &Path, file: &str, parse: F) -> Option<f64>
+where
+    F: Fn(&str) -> Option<f64>,
+{
+    let mut limit: Option<f64> = None;
+    for dir in dir.ancestors().take_while(|dir| dir.starts_with(root)) {
+        if let Some(value) = tokio::fs::read_to_string(dir.join(file))
+            .await
+            .ok()
+            .as_deref()
+            .and_then(&parse)
+        {
+            limit = Some(limit.map_or(value, |limit| limit.min(value)));
+        }
+    }
+    limit
+}
+
+// memory.max, 字节
+pub async fn memory_max(dir: &Path, root: &Path) -> Option<u64> {
+    effective_limit(dir, root, "memory.max", |v| parse_max(v).map(|v| v as f64))
+        .await
+        .map(|v| v as u64)
+}
+
+// cpu.max换算成百分比, 100%为一个核
+pub async fn cpu_max(dir: &Path, root: &Path) -> Option<f64> {
+    effective_limit(dir, root, "cpu.max", |v| {
+        let (quota, period) = v.trim().split_once(' ')?;
+        let period = period.parse::<f64>().ok()?;
+        Some(parse_max(quota)? as f64 / period * 100.0)
+    })
+    .await
+}
+
+// 用户指定的cgroup路径, 可以是挂载点下的完整路径, 也可以是/proc/<pid>/cgroup中的相对路径.
+// 不允许`..`, 否则可以指向挂载点之外
+pub fn resolve(root: &Path, path: &Path) -> anyhow::Result<PathBuf> {
+    if path
+        .components()
+        .any(|component| component == Component::ParentDir)
+    {
+        anyhow::bail!(
+            "invalid cgroup path {}, `..` is not allowed",
+            path.display()
+        );
+    }
+    if path.starts_with(root) {
+        Ok(path.to_owned())
+    } else {
+        Ok(root.join(path.strip_prefix("/").unwrap_or(path)))
+    }
+}
+
+// systemd unit所在的cgroup
+pub async fn unit_cgroup(root: &Path, unit: &str) -> anyhow::Result<PathBuf> {
+    let output = tokio::process::Command::new("systemctl")
+        .args(["show", "--property=ControlGroup", "--value", unit])
+        .output()
+        .await?;
+    let path = String::from_utf8(output.stdout)?;
+    let path = path.trim();
+    if !output.status.success() || path.is_empty() {
+        anyhow::bail!("unit {} has no control group, is it running?", unit);
+    }
+    resolve(root, Path::new(path))
+}
+
+// memory.stat, cpu.stat等`key value`格式的文件
+pub fn parse_flat_keyed(content: &str) -> HashMap<String, u64> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let (key, value) = line.split_once(' ')?;
+            Some((key.to_owned(), value.trim().parse().ok()?))
+        })
+        .collect()
+}
+
+// io.stat等`key sub_key=value ...`格式的文件
+pub fn parse_nested_keyed(content: &str) -> HashMap<String, HashMap<String, u64>> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let key = fields.next()?;
+            let values = fields
+                .filter_map(|field| {
+                    let (key, value) = field.split_once('=')?;
+                    Some((key.to_owned(), value.parse().ok()?))
+                })
+                .collect();
+            Some((key.to_owned(), values))
+        })
+        .collect()
+}
+
+// *.pressure和/proc/pressure/*
+pub fn parse_pressure(content: &str) -> Option<Pressure> {
+    let mut some = None;
+    let mut full = None;
+    for line in content.lines() {
+        let mut fields = line.split_whitespace();
+        let kind = fields.next()?;
+        let values = fields
+            .filter_map(|field| field.split_once('='))
+            .collect::<HashMap<_, _>>();
+        let line = PressureLine {
+            avg10: values.get("avg10")?.parse().ok()?,
+            avg60: values.get("avg60")?.parse().ok()?,
+            avg300: values.get("avg300")?.parse().ok()?,
+            total: values.get("total")?.parse().ok()?,
+        };
+        match kind {
+            "some" => some = Some(line),
+            "full" => full = Some(line),
+            _ => {}
+        }
+    }
+    Some(Pressure { some: some?, full })
+}
+
+async fn read(dir: &Path, file: &str) -> Option<String> {
+    tokio::fs::read_to_string(dir.join(file)).await.ok()
+}
+
+// 没有启用对应控制器的文件不存在, 只有memory.current是必须的
+pub async fn stat(dir: &Path) -> anyhow::Result<CgroupStat> {
+    let memory_current = tokio::fs::read_to_string(dir.join("memory.current"))
+        .await
+        .map_err(|err| {
+            anyhow::anyhow!(
+                "failed to read {}: {}, is the memory controller enabled?",
+                dir.join("memory.current").display(),
+                err
+            )
+        })?
+        .trim()
+        .parse()?;
+
+    Ok(CgroupStat {
+        memory_current,
+        memory_stat: read(dir, "memory.stat")
+            .await
+            .as_deref()
+            .map(parse_flat_keyed)
+            .unwrap_or_default(),
+        cpu_stat: read(dir, "cpu.stat")
+            .await
+            .as_deref()
+            .map(parse_flat_keyed)
+            .unwrap_or_default(),
+        io_stat: io_stat(dir).await,
+        pids_current: read(dir, "pids.current")
+            .await
+            .and_then(|v| v.trim().parse().ok()),
+        pressure: pressure(dir).await,
+    })
+}
+
+// cgroup的cpu/memory/io.pressure, 内核没有开启PSI时为空
+pub async fn pressure(dir: &Path) -> HashMap<String, Pressure> {
+    let mut pressure = HashMap::new();
+    for resource in ["cpu", "memory", "io"] {
+        if let Some(p) = read(dir, &format!("{}.pressure", resource))
+            .await
+            .as_deref()
+            .and_then(parse_pressure)
+        {
+            pressure.insert(resource.to_owned(), p);
+        }
+    }
+    pressure
+}
+
+// 没有启用io控制器时为空
+pub async fn io_stat(dir: &Path) -> HashMap<String, HashMap<String, u64>> {
+    read(dir, "io.stat")
+        .await
+        .as_deref()
+        .map(parse_nested_keyed)
+        .unwrap_or_default()
+}
+
+// io.stat中每个设备的读写字节. io.stat按`major:minor`区分设备, 转换为设备名
+pub async fn devices(io_stat: &HashMap<String, HashMap<String, u64>>) -> HashMap<String, DeviceIo> {
+    let mut devices = HashMap::new();
+    for (dev, stat) in io_stat {
+        let get = |key: &str| stat.get(key).copied().unwrap_or(0);
+        devices.insert(
+            device_name(dev).await,
+            DeviceIo {
+                read: get("rbytes"),
+                written: get("wbytes"),
+            },
+        );
+    }
+    devices
+}
+
+// /sys/dev/block/<major:minor>/uevent中的DEVNAME, 找不到时使用`major:minor`
+async fn device_name(dev: &str) -> String {
+    tokio::fs::read_to_string(Path::new("/sys/dev/block").join(dev).join("uevent"))
+        .await
+        .ok()
+        .and_then(|uevent| {
+            uevent
+                .lines()
+                .find_map(|line| line.strip_prefix("DEVNAME="))
+                .map(str::to_owned)
+        })
+        .unwrap_or_else(|| dev.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PRESSURE: &str = "some avg10=1.50 avg60=0.80 avg300=0.20 total=12345\n\
+                            full avg10=0.50 avg60=0.10 avg300=0.00 total=678\n";
+
+    fn write(dir: &Path, file: &str, content: &str) {
+        std::fs::write(dir.join(file), content).unwrap();
+    }
+
+    #[test]
+    fn flat_keyed_skips_malformed_lines() {
+        let stat = parse_flat_keyed("anon 100\nfile 200\nno_value\nshmem x\n\nsock 3\n");
+        assert_eq!(stat.len(), 3);
+        assert_eq!(stat["anon"], 100);
+        assert_eq!(stat["file"], 200);
+        assert_eq!(stat["sock"], 3);
+    }
+
+    #[test]
+    fn nested_keyed() {
+        let stat = parse_nested_keyed("8:0 rbytes=10 wbytes=20 junk rios=x\n259:0 rbytes=1\n");
+        assert_eq!(stat["8:0"].len(), 2);
+        assert_eq!(stat["8:0"]["rbytes"], 10);
+        assert_eq!(stat["8:0"]["wbytes"], 20);
+        assert_eq!(stat["259:0"]["rbytes"], 1);
+    }
+
+    #[test]
+    fn pressure_lines() {
+        let pressure = parse_pressure(PRESSURE).unwrap();
+        assert_eq!(pressure.some.avg10, 1.5);
+        assert_eq!(pressure.some.total, 12345);
+        assert_eq!(pressure.full.unwrap().total, 678);
+
+        // cpu.pressure在旧内核上没有full
+        let pressure = parse_pressure("some avg10=0.00 avg60=0.00 avg300=0.00 total=1\n").unwrap();
+        assert!(pressure.full.is_none());
+
+        assert!(parse_pressure("some avg10=abc avg60=0.00 avg300=0.00 total=1\n").is_none());
+        assert!(parse_pressure("").is_none());
+    }
+
+    #[test]
+    fn max() {
+        assert_eq!(parse_max("max\n"), None);
+        assert_eq!(parse_max("1024\n"), Some(1024));
+        assert_eq!(parse_max("garbage"), None);
+    }
+
+    #[test]
+    fn resolve_paths() {
+        let root = Path::new("/sys/fs/cgroup");
+        assert_eq!(
+            resolve(root, Path::new("/system.slice/foo.service")).unwrap(),
+            Path::new("/sys/fs/cgroup/system.slice/foo.service")
+        );
+        assert_eq!(
+            resolve(root, Path::new("/sys/fs/cgroup/user.slice")).unwrap(),
+            Path::new("/sys/fs/cgroup/user.slice")
+        );
+        assert!(resolve(root, Path::new("../..")).is_err());
+        assert!(resolve(root, Path::new("/sys/fs/cgroup/../../etc")).is_err());
+        assert!(resolve(root, Path::new("/system.slice/../../etc")).is_err());
+    }
+
+    #[tokio::test]
+    async fn stat_of_fake_cgroup() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().join("system.slice").join("foo.service");
+        std::fs::create_dir_all(&dir).unwrap();
+
+        write(&dir, "memory.current", "1048576\n");
+        write(&dir, "memory.stat", "anon 100\nfile 200\nbroken\n");
+        write(
+            &dir,
+            "cpu.stat",
+            "usage_usec 5000\nuser_usec 3000\nsystem_usec x\n",
+        );
+        write(&dir, "memory.pressure", PRESSURE);
+        write(&dir, "cpu.pressure", "some avg10=bad\n");
+        write(&dir, "io.stat", "8:0 rbytes=10 wbytes=20 rios=1\n");
+        // pids.current和io.pressure不存在
+
+        let stat = stat(&dir).await.unwrap();
+        assert_eq!(stat.memory_current, 1048576);
+        assert_eq!(stat.memory_stat.len(), 2);
+        assert_eq!(stat.memory_stat["anon"], 100);
+        assert_eq!(stat.cpu_stat.len(), 2);
+        assert_eq!(stat.cpu_stat["usage_usec"], 5000);
+        assert_eq!(stat.io_stat["8:0"]["wbytes"], 20);
+        assert_eq!(stat.pids_current, None);
+        assert_eq!(stat.pressure.len(), 1);
+        assert_eq!(stat.pressure["memory"].some.total, 12345);
+    }
+
+    #[tokio::test]
+    async fn stat_requires_memory_current() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "memory.stat", "anon 100\n");
+        assert!(stat(dir.path()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn effective_limits() {
+        let root = tempfile::tempdir().unwrap();
+        let parent = root.path().join("system.slice");
+        let dir = parent.join("foo.service");
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // 没有限制
+        write(&dir, "memory.max", "max\n");
+        write(&dir, "cpu.max", "max 100000\n");
+        assert_eq!(memory_max(&dir, root.path()).await, None);
+        assert_eq!(cpu_max(&dir, root.path()).await, None);
+
+        // 上级cgroup的限制更小时生效
+        write(&parent, "memory.max", "1000\n");
+        write(&dir, "cpu.max", "50000 100000\n");
+        write(&parent, "cpu.max", "200000 100000\n");
+        assert_eq!(memory_max(&dir, root.path()).await, Some(1000));
+        assert_eq!(cpu_max(&dir, root.path()).await, Some(50.0));
+
+        // 格式错误的文件忽略
+        write(&dir, "memory.max", "lots\n");
+        write(&parent, "memory.max", "\n");
+        assert_eq!(memory_max(&dir, root.path()).await, None);
+    }
+}
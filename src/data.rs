@@ -1,14 +1,206 @@
-use std::collections::HashMap;
-use std::fmt::{Debug, Formatter};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::{Debug, Display, Formatter};
 use std::time::Duration;
 
+use bincode::de::read::Reader;
+use bincode::de::Decoder;
+use bincode::error::DecodeError;
 use bincode::{Decode, Encode};
 use byte_unit::Byte;
+use chrono::{Local, TimeZone};
 #[cfg(target_os = "linux")]
 use heim::process::os::linux::MemoryExt;
 use serde::{Deserialize, Serialize};
 
+// 中间文件的文件头, 见`crate::store`
+#[derive(Debug, Clone, Encode, Serialize, Deserialize)]
+pub struct Header {
+    pub version: u32,
+    // 记录时机器的逻辑核数
+    pub cores: u32,
+    // 以下字段是后来追加的
+    // 记录进程时开始记录的进程, 记录cgroup时为None
+    pub process: Option<ProcessInfo>,
+}
+
+impl Decode for Header {
+    fn decode<D: Decoder>(decoder: &mut D) -> Result<Self, DecodeError> {
+        Ok(Header {
+            version: Decode::decode(decoder)?,
+            cores: Decode::decode(decoder)?,
+            process: decode_appended(decoder)?,
+        })
+    }
+}
+
+// 进程的信息, 读取失败的项为None
+#[derive(Debug, Clone, Default, Encode, Decode, Serialize, Deserialize)]
+pub struct ProcessInfo {
+    pub pid: u32,
+    pub exe: Option<String>,
+    pub cmdline: Vec<String>,
+    pub cwd: Option<String>,
+    // 实际的用户和组. linux only
+    pub uid: Option<u32>,
+    pub user: Option<String>,
+    pub gid: Option<u32>,
+    pub group: Option<String>,
+    pub ppid: Option<u32>,
+    // unix毫秒时间戳
+    pub start_time: Option<u64>,
+    // unix only
+    pub nice: Option<i32>,
+    // `record --env`允许记录的环境变量
+    pub env: BTreeMap<String, String>,
+}
+
+impl Display for ProcessInfo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "pid: {}", self.pid)?;
+        let optional = |value: Option<String>| value.unwrap_or_else(|| "-".to_owned());
+        writeln!(f, "exe: {}", optional(self.exe.clone()))?;
+        writeln!(f, "cmdline: {}", self.cmdline.join(" "))?;
+        writeln!(f, "cwd: {}", optional(self.cwd.clone()))?;
+        writeln!(
+            f,
+            "user: {}({})",
+            optional(self.user.clone()),
+            optional(self.uid.map(|uid| uid.to_string()))
+        )?;
+        writeln!(
+            f,
+            "group: {}({})",
+            optional(self.group.clone()),
+            optional(self.gid.map(|gid| gid.to_string()))
+        )?;
+        writeln!(
+            f,
+            "ppid: {}",
+            optional(self.ppid.map(|ppid| ppid.to_string()))
+        )?;
+        writeln!(
+            f,
+            "start time: {}",
+            optional(
+                self.start_time
+                    .and_then(|time| Local.timestamp_millis_opt(time as i64).single())
+                    .map(|time| time.to_rfc3339())
+            )
+        )?;
+        write!(
+            f,
+            "nice: {}",
+            optional(self.nice.map(|nice| nice.to_string()))
+        )?;
+        for (name, value) in &self.env {
+            write!(f, "\nenv: {}={}", name, value)?;
+        }
+        Ok(())
+    }
+}
+
+bincode::impl_borrow_decode!(Header);
+
+// 中间文件中文件头之后的每一条记录. 新的类型只能追加在末尾
+// 每次只读写一条记录, 不需要把Data放到堆上
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone, Encode, Decode, Serialize, Deserialize)]
+pub enum Record {
+    Data(Data),
+    Marker(Marker),
+    Segment(Segment),
+    Error(CollectError),
+    Event(Event),
+}
+
+// 记录时注入的标记, 在图表上绘制为竖线
+#[derive(Debug, Clone, Encode, Decode, Serialize, Deserialize)]
+pub struct Marker {
+    // unix毫秒时间戳
+    pub time: u64,
+    pub label: String,
+}
+
+// 触发模式下每一段的开始, 之后是触发前后的采样. 段之间的采样没有保存
+#[derive(Debug, Clone, Encode, Decode, Serialize, Deserialize)]
+pub struct Segment {
+    // 触发的时间, unix毫秒时间戳
+    pub time: u64,
+    // 满足的条件
+    pub reason: String,
+}
+
+// 一次采样中某一项采集失败, 这一项在这次采样中没有值
 #[derive(Debug, Clone, Encode, Decode, Serialize, Deserialize)]
+pub struct CollectError {
+    // unix毫秒时间戳
+    pub time: u64,
+    // 失败的采集项, 整个采样失败时为"collection"
+    pub collector: String,
+    pub message: String,
+}
+
+// 记录期间进程的变化
+#[derive(Debug, Clone, Encode, Decode, Serialize, Deserialize)]
+pub struct Event {
+    // unix毫秒时间戳
+    pub time: u64,
+    pub kind: EventKind,
+}
+
+#[derive(Debug, Clone, Encode, Decode, Serialize, Deserialize)]
+pub enum EventKind {
+    // 执行了新的程序(命令行变化)
+    Exec {
+        exe: Option<String>,
+        cmdline: Vec<String>,
+    },
+    // nice值变化
+    Priority {
+        nice: i32,
+    },
+    // 退出码或者结束进程的信号, 进程已经被回收时不知道
+    Exit {
+        code: Option<i32>,
+        signal: Option<i32>,
+    },
+    // 跟随重启时开始记录的新进程
+    Start(ProcessInfo),
+}
+
+impl Display for EventKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EventKind::Exec { cmdline, .. } => write!(f, "exec: {}", cmdline.join(" ")),
+            EventKind::Priority { nice } => write!(f, "nice: {}", nice),
+            EventKind::Exit {
+                code: Some(code), ..
+            } => write!(f, "exit: {}", code),
+            EventKind::Exit {
+                signal: Some(signal),
+                ..
+            } => write!(f, "exit: signal {}", signal),
+            EventKind::Exit { .. } => write!(f, "exit"),
+            EventKind::Start(info) => write!(f, "start: {}", info.pid),
+        }
+    }
+}
+
+// 版本2之前的Record, 读取时转换为`Record`
+#[derive(Decode)]
+pub enum RecordV1 {
+    Data(DataV1),
+}
+
+impl From<RecordV1> for Record {
+    fn from(record: RecordV1) -> Self {
+        match record {
+            RecordV1::Data(data) => Record::Data(data.0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Encode, Serialize, Deserialize)]
 pub struct Data {
     pub memory: Memory,
     pub cpu_time: CpuTime,
@@ -17,6 +209,155 @@ pub struct Data {
 
     // linux only
     pub net_io: HashMap<String, NetIo>,
+
+    // 以下字段是后来追加的, 旧文件中没有, 见`Decode for Data`
+    // 采样时间, unix毫秒时间戳
+    pub time: Option<u64>,
+    // linux only
+    pub sched: Option<Sched>,
+    // linux only, 需要在record时开启
+    pub fd: Option<Fd>,
+    // linux only, 只在第一次采样和发生变化时记录
+    pub limits: Option<Limits>,
+    // 只在记录cgroup时存在, 见`CgroupStat`
+    pub cgroup: Option<CgroupStat>,
+    // linux only, 需要在record时开启
+    pub psi: Option<Psi>,
+    // 需要在record时开启
+    pub host: Option<Host>,
+    // linux only, 需要在record时开启
+    pub cores: Option<CpuCores>,
+    // linux only, 需要在record时开启
+    pub io_attribution: Option<IoAttribution>,
+    // 应用程序发送的自定义指标, 见`crate::metrics`
+    pub custom: Option<BTreeMap<String, f64>>,
+}
+
+// 追加的字段只能放在Data和Header的末尾.
+// 每条记录都带有长度, 旧文件的记录读到末尾时, 剩下的字段使用默认值
+impl Decode for Data {
+    fn decode<D: Decoder>(decoder: &mut D) -> Result<Self, DecodeError> {
+        decode_data::<D, Io>(decoder)
+    }
+}
+
+bincode::impl_borrow_decode!(Data);
+
+// 版本2之前的Data, Io的布局不同
+pub struct DataV1(pub Data);
+
+impl Decode for DataV1 {
+    fn decode<D: Decoder>(decoder: &mut D) -> Result<Self, DecodeError> {
+        decode_data::<D, IoV1>(decoder).map(DataV1)
+    }
+}
+
+bincode::impl_borrow_decode!(DataV1);
+
+fn decode_data<D, I>(decoder: &mut D) -> Result<Data, DecodeError>
+where
+    D: Decoder,
+    I: Decode + Into<Io>,
+{
+    Ok(Data {
+        memory: Decode::decode(decoder)?,
+        cpu_time: Decode::decode(decoder)?,
+        cpu_usage: Decode::decode(decoder)?,
+        io: I::decode(decoder)?.into(),
+        net_io: Decode::decode(decoder)?,
+        time: decode_appended(decoder)?,
+        sched: decode_appended(decoder)?,
+        fd: decode_appended(decoder)?,
+        limits: decode_appended(decoder)?,
+        cgroup: decode_appended(decoder)?,
+        psi: decode_appended(decoder)?,
+        host: decode_appended(decoder)?,
+        cores: decode_appended(decoder)?,
+        io_attribution: decode_appended(decoder)?,
+        custom: decode_appended(decoder)?,
+    })
+}
+
+// 最早记录fd的版本的Data, Fd中带有RLIMIT_NOFILE, 之后移到了`Limits`.
+// 这种记录在fd之后结束, 见`crate::store::decode_legacy`
+pub struct DataFdV1(pub Data);
+
+impl Decode for DataFdV1 {
+    fn decode<D: Decoder>(decoder: &mut D) -> Result<Self, DecodeError> {
+        let memory = Decode::decode(decoder)?;
+        let cpu_time = Decode::decode(decoder)?;
+        let cpu_usage = Decode::decode(decoder)?;
+        let io = IoV1::decode(decoder)?.into();
+        let net_io = Decode::decode(decoder)?;
+        let time = decode_appended(decoder)?;
+        let sched = decode_appended(decoder)?;
+        let fd: Option<FdV1> = decode_appended(decoder)?;
+        Ok(DataFdV1(Data {
+            memory,
+            cpu_time,
+            cpu_usage,
+            io,
+            net_io,
+            time,
+            sched,
+            limits: fd.as_ref().map(|fd| Limits {
+                address_space: None,
+                data: None,
+                nofile: fd.limit_soft,
+                nproc: None,
+                memory_max: None,
+                cpu_max: None,
+            }),
+            fd: fd.map(Fd::from),
+            cgroup: None,
+            psi: None,
+            host: None,
+            cores: None,
+            io_attribution: None,
+            custom: None,
+        }))
+    }
+}
+
+bincode::impl_borrow_decode!(DataFdV1);
+
+fn decode_appended<T, D>(decoder: &mut D) -> Result<T, DecodeError>
+where
+    T: Decode + Default,
+    D: Decoder,
+{
+    if decoder.reader().peek_read(1).is_none() {
+        Ok(T::default())
+    } else {
+        T::decode(decoder)
+    }
+}
+
+impl Data {
+    // 与上一次采样之间的时间间隔
+    pub fn elapsed_since(&self, prev: &Data) -> Option<Duration> {
+        Some(Duration::from_millis(self.time?.checked_sub(prev.time?)?))
+    }
+}
+
+// 出现过的全部自定义指标
+pub fn custom_names(data: &[Data]) -> BTreeSet<&str> {
+    data.iter()
+        .filter_map(|data| data.custom.as_ref())
+        .flat_map(|custom| custom.keys().map(String::as_str))
+        .collect()
+}
+
+// 每个采样时生效的Limits. Limits只在变化时记录, 所以沿用之前最后一次记录的值
+pub fn effective_limits(data: &[Data]) -> Vec<Option<&Limits>> {
+    data.iter()
+        .scan(None, |limits, data| {
+            if let Some(l) = &data.limits {
+                *limits = Some(l);
+            }
+            Some(*limits)
+        })
+        .collect()
 }
 
 #[derive(Clone, Encode, Decode, Serialize, Deserialize)]
@@ -41,14 +382,388 @@ pub struct CpuTime {
 
 #[derive(Clone, Encode, Decode, Serialize, Deserialize)]
 pub struct Io {
-    pub bytes_written: u64,
-    pub bytes_read: u64,
+    // 实际读写存储设备的字节. linux为/proc/<pid>/io的read_bytes/write_bytes, cgroup为io.stat
+    pub storage_written: u64,
+    pub storage_read: u64,
 
     // linux only
-    pub disk_written: Option<u64>,
-    pub disk_read: Option<u64>,
+    // read/write等系统调用读写的字节(rchar/wchar), 包括管道, 套接字和页缓存命中
+    pub chars_written: Option<u64>,
+    pub chars_read: Option<u64>,
+    // 读写系统调用的次数
     pub syscall_written: Option<u64>,
     pub syscall_read: Option<u64>,
+    // 写入页缓存后在落盘前被截断或删除, 没有实际写入存储设备的字节
+    pub cancelled_write_bytes: Option<u64>,
+}
+
+// 版本2之前的Io. 字段的顺序与现在相同, 只是名字有误导性:
+// `bytes_*`是存储设备的字节, `disk_*`其实是rchar/wchar
+#[derive(Decode)]
+struct IoV1 {
+    bytes_written: u64,
+    bytes_read: u64,
+    disk_written: Option<u64>,
+    disk_read: Option<u64>,
+    syscall_written: Option<u64>,
+    syscall_read: Option<u64>,
+}
+
+impl From<IoV1> for Io {
+    fn from(io: IoV1) -> Self {
+        Io {
+            storage_written: io.bytes_written,
+            storage_read: io.bytes_read,
+            chars_written: io.disk_written,
+            chars_read: io.disk_read,
+            syscall_written: io.syscall_written,
+            syscall_read: io.syscall_read,
+            cancelled_write_bytes: None,
+        }
+    }
+}
+
+// 上下文切换, 缺页和调度延迟, 都是累计值
+#[derive(Debug, Clone, Encode, Decode, Serialize, Deserialize)]
+pub struct Sched {
+    pub voluntary_ctxt_switches: u64,
+    pub nonvoluntary_ctxt_switches: u64,
+    pub minor_faults: u64,
+    pub major_faults: u64,
+
+    // 在运行队列中等待的时间, 纳秒. 需要内核开启schedstat
+    pub run_delay: Option<u64>,
+    pub timeslices: Option<u64>,
+}
+
+// 两次采样之间Sched的每秒变化量
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SchedRate {
+    pub voluntary_ctxt_switches: f64,
+    pub nonvoluntary_ctxt_switches: f64,
+    pub minor_faults: f64,
+    pub major_faults: f64,
+
+    // 每秒在运行队列中等待的微秒数
+    pub run_delay: Option<f64>,
+}
+
+impl Sched {
+    pub fn rate(&self, prev: &Sched, elapsed: Duration) -> SchedRate {
+        let secs = elapsed.as_secs_f64();
+        let per_sec = |now: u64, last: u64| {
+            if secs > 0.0 {
+                now.saturating_sub(last) as f64 / secs
+            } else {
+                0.0
+            }
+        };
+
+        SchedRate {
+            voluntary_ctxt_switches: per_sec(
+                self.voluntary_ctxt_switches,
+                prev.voluntary_ctxt_switches,
+            ),
+            nonvoluntary_ctxt_switches: per_sec(
+                self.nonvoluntary_ctxt_switches,
+                prev.nonvoluntary_ctxt_switches,
+            ),
+            minor_faults: per_sec(self.minor_faults, prev.minor_faults),
+            major_faults: per_sec(self.major_faults, prev.major_faults),
+            run_delay: self
+                .run_delay
+                .zip(prev.run_delay)
+                .map(|(now, last)| per_sec(now, last) / 1_000.0),
+        }
+    }
+}
+
+// 打开的文件描述符, 按类型分类
+#[derive(Debug, Clone, Default, Encode, Decode, Serialize, Deserialize)]
+pub struct Fd {
+    pub total: u64,
+    pub file: u64,
+    pub socket: u64,
+    pub pipe: u64,
+    pub anon_inode: u64,
+    pub eventfd: u64,
+
+    // 该进程拥有的socket, 按状态计数
+    pub tcp: HashMap<String, u64>,
+    pub udp: HashMap<String, u64>,
+}
+
+// 最早记录fd的版本的Fd, 见`DataFdV1`
+#[derive(Decode)]
+struct FdV1 {
+    total: u64,
+    file: u64,
+    socket: u64,
+    pipe: u64,
+    anon_inode: u64,
+    eventfd: u64,
+    // RLIMIT_NOFILE, 软限制保存到`Limits::nofile`, 硬限制不再记录
+    limit_soft: Option<u64>,
+    _limit_hard: Option<u64>,
+    tcp: HashMap<String, u64>,
+    udp: HashMap<String, u64>,
+}
+
+impl From<FdV1> for Fd {
+    fn from(fd: FdV1) -> Self {
+        Fd {
+            total: fd.total,
+            file: fd.file,
+            socket: fd.socket,
+            pipe: fd.pipe,
+            anon_inode: fd.anon_inode,
+            eventfd: fd.eventfd,
+            tcp: fd.tcp,
+            udp: fd.udp,
+        }
+    }
+}
+
+// 进程在各个核上的分布
+#[derive(Debug, Clone, Encode, Decode, Serialize, Deserialize)]
+pub struct CpuCores {
+    // 允许运行的核(sched affinity)
+    pub affinity: Vec<u32>,
+    // 最后一次运行所在的核
+    pub last_cpu: u32,
+    // 自上一次采样以来在每个核上的使用率, 百分比.
+    // 按每个线程最后一次运行所在的核估算
+    pub usage: BTreeMap<u32, f32>,
+}
+
+// 按文件和块设备划分的I/O
+#[derive(Debug, Clone, Default, Encode, Decode, Serialize, Deserialize)]
+pub struct IoAttribution {
+    // 打开的普通文件的路径. 同一个文件的多个fd相加
+    pub files: HashMap<String, FilePosition>,
+    // 块设备的名字, 来自所在cgroup的io.stat, 包括cgroup中其他进程的读写
+    pub devices: HashMap<String, DeviceIo>,
+}
+
+// /proc/<pid>/fdinfo中的读写位置.
+// 顺序读写时位置的增长量就是读写的字节, pread/pwrite和mmap不会改变位置
+#[derive(Debug, Clone, Default, Encode, Decode, Serialize, Deserialize)]
+pub struct FilePosition {
+    pub pos: u64,
+    // 打开方式
+    pub read: bool,
+    pub write: bool,
+}
+
+impl FilePosition {
+    pub fn mode(&self) -> &'static str {
+        match (self.read, self.write) {
+            (true, true) => "rw",
+            (false, true) => "w",
+            _ => "r",
+        }
+    }
+}
+
+// 累计值, 字节
+#[derive(Debug, Clone, Default, Encode, Decode, Serialize, Deserialize)]
+pub struct DeviceIo {
+    pub read: u64,
+    pub written: u64,
+}
+
+// 每个文件在每个采样时的累计读写进度, 即位置增长量之和.
+// 记录期间打开的文件从位置0开始计算, 关闭后重新打开时累加到之前的进度上.
+// 位置变小(seek)时不计入, 按最终进度从大到小排列
+pub fn file_progress(data: &[Data]) -> Vec<(String, &'static str, Vec<u64>)> {
+    let mut progress: HashMap<String, (&'static str, Vec<u64>)> = HashMap::new();
+    let mut totals: HashMap<&str, u64> = HashMap::new();
+    for (i, window) in data.windows(2).enumerate() {
+        let (prev, now) = match (&window[0].io_attribution, &window[1].io_attribution) {
+            (Some(prev), Some(now)) => (prev, now),
+            _ => continue,
+        };
+        for (path, file) in &now.files {
+            let grown = match prev.files.get(path) {
+                Some(prev) => file.pos.saturating_sub(prev.pos),
+                // 两次采样之间打开的
+                None => file.pos,
+            };
+            let total = totals.entry(path).or_insert(0);
+            *total += grown;
+            let (_, series) = progress
+                .entry(path.clone())
+                .or_insert_with(|| (file.mode(), vec![0; data.len()]));
+            series[i + 1] = *total;
+        }
+    }
+
+    let mut progress = progress
+        .into_iter()
+        .map(|(path, (mode, mut series))| {
+            // 文件关闭之后保持最后的进度
+            for i in 1..series.len() {
+                series[i] = series[i].max(series[i - 1]);
+            }
+            (path, mode, series)
+        })
+        .filter(|(_, _, series)| series.last().copied().unwrap_or(0) > 0)
+        .collect::<Vec<_>>();
+    progress.sort_by(|a, b| b.2.last().cmp(&a.2.last()).then_with(|| a.0.cmp(&b.0)));
+    progress
+}
+
+// 每个块设备在每个采样时自记录开始以来的读写字节, 按读写总量从大到小排列
+pub fn device_io(data: &[Data]) -> Vec<(String, Vec<DeviceIo>)> {
+    let mut first: HashMap<&str, &DeviceIo> = HashMap::new();
+    let mut devices: HashMap<&str, Vec<DeviceIo>> = HashMap::new();
+    for (i, attribution) in data.iter().enumerate() {
+        let attribution = match &attribution.io_attribution {
+            Some(attribution) => attribution,
+            None => continue,
+        };
+        for (name, io) in &attribution.devices {
+            let first = *first.entry(name).or_insert(io);
+            let series = devices
+                .entry(name)
+                .or_insert_with(|| vec![DeviceIo::default(); data.len()]);
+            series[i] = DeviceIo {
+                read: io.read.saturating_sub(first.read),
+                written: io.written.saturating_sub(first.written),
+            };
+        }
+    }
+
+    let total = |series: &[DeviceIo]| {
+        series
+            .iter()
+            .map(|io| io.read + io.written)
+            .max()
+            .unwrap_or(0)
+    };
+    let mut devices = devices
+        .into_iter()
+        .map(|(name, series)| (name.to_owned(), series))
+        .collect::<Vec<_>>();
+    devices.sort_by(|a, b| total(&b.1).cmp(&total(&a.1)).then_with(|| a.0.cmp(&b.0)));
+    devices
+}
+
+// 与进程同一时刻的系统整体数据
+#[derive(Debug, Clone, Encode, Decode, Serialize, Deserialize)]
+pub struct Host {
+    // 百分比, 与CpuUsage相同, 100%为一个核
+    pub cpu_usage: f32,
+    pub cpu_usage_per_core: Vec<f32>,
+    // 1, 5, 15分钟. unix only
+    pub load_average: Option<[f64; 3]>,
+
+    pub memory_total: u64,
+    pub memory_available: u64,
+    pub swap_total: u64,
+    pub swap_used: u64,
+
+    // 累计值, 全部物理磁盘的总和
+    pub disk_read: u64,
+    pub disk_written: u64,
+    // 累计值, 除了lo以外全部网卡的总和
+    pub net_recv: u64,
+    pub net_sent: u64,
+}
+
+// 进程的资源限制, None为不限制
+#[derive(Debug, Clone, PartialEq, Encode, Decode, Serialize, Deserialize)]
+pub struct Limits {
+    // rlimit的软限制
+    pub address_space: Option<u64>,
+    pub data: Option<u64>,
+    pub nofile: Option<u64>,
+    pub nproc: Option<u64>,
+
+    // 进程所在cgroup v2(包括上级)的memory.max, 字节
+    pub memory_max: Option<u64>,
+    // 进程所在cgroup v2(包括上级)的cpu.max, 百分比, 100%为一个核
+    pub cpu_max: Option<f64>,
+}
+
+// 记录cgroup v2时的采样. 为了沿用进程的图表, Data的其他字段填充为:
+// memory.rss为memory.current, memory.vms为0, memory.shared/data为memory.stat的shmem/anon;
+// cpu_time和cpu_usage来自cpu.stat; io.bytes_read/bytes_written为io.stat中全部设备的总和
+#[derive(Debug, Clone, Encode, Decode, Serialize, Deserialize)]
+pub struct CgroupStat {
+    pub memory_current: u64,
+    pub memory_stat: HashMap<String, u64>,
+    pub cpu_stat: HashMap<String, u64>,
+    // 设备号(major:minor) -> rbytes/wbytes/rios/wios/dbytes/dios
+    pub io_stat: HashMap<String, HashMap<String, u64>>,
+    pub pids_current: Option<u64>,
+
+    // cpu/memory/io -> *.pressure
+    pub pressure: HashMap<String, Pressure>,
+}
+
+// 采样时的Pressure Stall Information
+#[derive(Debug, Clone, Encode, Decode, Serialize, Deserialize)]
+pub struct Psi {
+    // /proc/pressure/*
+    pub system: HashMap<String, Pressure>,
+    // 进程(或记录的cgroup)所在cgroup的*.pressure
+    pub cgroup: HashMap<String, Pressure>,
+}
+
+impl Psi {
+    // 全部的some/full, 名称为`<system|cgroup> <resource> <some|full>`
+    pub fn lines(&self) -> impl Iterator<Item = (String, &PressureLine)> {
+        [("system", &self.system), ("cgroup", &self.cgroup)]
+            .into_iter()
+            .flat_map(|(scope, pressure)| {
+                pressure.iter().flat_map(move |(resource, pressure)| {
+                    std::iter::once(("some", &pressure.some))
+                        .chain(pressure.full.as_ref().map(|full| ("full", full)))
+                        .map(move |(kind, line)| (format!("{} {} {}", scope, resource, kind), line))
+                })
+            })
+    }
+}
+
+// 整个记录期间每种压力的停顿时间占比(百分比), 由首尾两次采样的total计算
+pub fn stall_summary(data: &[Data]) -> BTreeMap<String, f64> {
+    let samples = data
+        .iter()
+        .filter_map(|data| Some((data.time?, data.psi.as_ref()?)))
+        .collect::<Vec<_>>();
+    let ((first_time, first), (last_time, last)) = match (samples.first(), samples.last()) {
+        (Some(first), Some(last)) if last.0 > first.0 => (first, last),
+        _ => return BTreeMap::new(),
+    };
+    // 毫秒 -> 微秒
+    let elapsed = (last_time - first_time) as f64 * 1000.0;
+
+    let first = first.lines().collect::<HashMap<_, _>>();
+    last.lines()
+        .filter_map(|(name, line)| {
+            let stalled = line.total.saturating_sub(first.get(&name)?.total);
+            Some((name, stalled as f64 / elapsed * 100.0))
+        })
+        .collect()
+}
+
+// Pressure Stall Information
+#[derive(Debug, Clone, Encode, Decode, Serialize, Deserialize)]
+pub struct Pressure {
+    pub some: PressureLine,
+    // cpu在旧内核上没有full
+    pub full: Option<PressureLine>,
+}
+
+#[derive(Debug, Clone, Encode, Decode, Serialize, Deserialize)]
+pub struct PressureLine {
+    // 百分比
+    pub avg10: f32,
+    pub avg60: f32,
+    pub avg300: f32,
+    // 累计停顿时间, 微秒
+    pub total: u64,
 }
 
 #[derive(Clone, Encode, Decode, Serialize, Deserialize)]
@@ -100,13 +815,14 @@ impl Debug for NetIo {
 impl From<heim::process::IoCounters> for Io {
     fn from(io: heim::process::IoCounters) -> Self {
         Io {
-            bytes_written: io.bytes_written().get::<heim::units::information::byte>(),
-            bytes_read: io.bytes_read().get::<heim::units::information::byte>(),
+            storage_written: io.bytes_written().get::<heim::units::information::byte>(),
+            storage_read: io.bytes_read().get::<heim::units::information::byte>(),
 
-            disk_written: None,
-            disk_read: None,
+            chars_written: None,
+            chars_read: None,
             syscall_written: None,
             syscall_read: None,
+            cancelled_write_bytes: None,
         }
     }
 }
@@ -115,15 +831,25 @@ impl Debug for Io {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         let to_string = |x: Byte| x.get_appropriate_unit(false).to_string();
         f.debug_struct("I/O")
-            .field("bytes_written", &to_string(Byte::from(self.bytes_written)))
-            .field("bytes_read", &to_string(Byte::from(self.bytes_read)))
             .field(
-                "disk_written",
-                &self.disk_written.map(Byte::from).map(to_string),
+                "storage_written",
+                &to_string(Byte::from(self.storage_written)),
+            )
+            .field("storage_read", &to_string(Byte::from(self.storage_read)))
+            .field(
+                "chars_written",
+                &self.chars_written.map(Byte::from).map(to_string),
+            )
+            .field(
+                "chars_read",
+                &self.chars_read.map(Byte::from).map(to_string),
             )
-            .field("disk_read", &self.disk_read.map(Byte::from).map(to_string))
             .field("syscall_written", &self.syscall_written)
             .field("syscall_read", &self.syscall_read)
+            .field(
+                "cancelled_write_bytes",
+                &self.cancelled_write_bytes.map(Byte::from).map(to_string),
+            )
             .finish()
     }
 }
@@ -146,9 +872,10 @@ impl Debug for CpuTime {
     }
 }
 
+// 100%为一个核, 不除以核数, 否则在核数不同的机器上看到的值不一样
 impl Debug for CpuUsage {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}%", self.0.round() / num_cpus::get() as f32)
+        write!(f, "{}%", self.0.round())
     }
 }
 
@@ -201,3 +928,140 @@ impl Debug for Memory {
             .finish()
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    // 只有时间的采样
+    pub(crate) fn sample(time: Option<u64>) -> Data {
+        Data {
+            memory: Memory {
+                rss: 0,
+                vms: 0,
+                shared: None,
+                text: None,
+                data: None,
+            },
+            cpu_time: CpuTime {
+                user: 0.0,
+                system: 0.0,
+            },
+            cpu_usage: CpuUsage(0.0),
+            io: Io {
+                storage_written: 0,
+                storage_read: 0,
+                chars_written: None,
+                chars_read: None,
+                syscall_written: None,
+                syscall_read: None,
+                cancelled_write_bytes: None,
+            },
+            net_io: HashMap::new(),
+            time,
+            sched: None,
+            fd: None,
+            limits: None,
+            cgroup: None,
+            psi: None,
+            host: None,
+            cores: None,
+            io_attribution: None,
+            custom: None,
+        }
+    }
+
+    fn files(files: &[(&str, u64)]) -> Data {
+        Data {
+            io_attribution: Some(IoAttribution {
+                files: files
+                    .iter()
+                    .map(|(path, pos)| {
+                        let file = FilePosition {
+                            pos: *pos,
+                            read: true,
+                            write: false,
+                        };
+                        (path.to_string(), file)
+                    })
+                    .collect(),
+                devices: HashMap::new(),
+            }),
+            ..sample(None)
+        }
+    }
+
+    fn progress(data: &[Data]) -> Vec<(String, Vec<u64>)> {
+        file_progress(data)
+            .into_iter()
+            .map(|(path, _, series)| (path, series))
+            .collect()
+    }
+
+    #[test]
+    fn file_progress_grows_with_position() {
+        let data = [
+            files(&[("/a", 1000)]),
+            files(&[("/a", 1100)]),
+            files(&[("/a", 1300)]),
+        ];
+        // 记录开始前的位置不计入
+        assert_eq!(progress(&data), [("/a".to_owned(), vec![0, 100, 300])]);
+    }
+
+    #[test]
+    fn file_progress_sums_reopened_files() {
+        let data = [
+            files(&[("/a", 0)]),
+            files(&[("/a", 100)]),
+            files(&[]),
+            files(&[("/a", 20)]),
+            files(&[("/a", 50)]),
+            files(&[]),
+        ];
+        assert_eq!(
+            progress(&data),
+            [("/a".to_owned(), vec![0, 100, 100, 120, 150, 150])]
+        );
+    }
+
+    #[test]
+    fn file_progress_ignores_seeking_back() {
+        let data = [
+            files(&[("/a", 0), ("/b", 0)]),
+            files(&[("/a", 500), ("/b", 10)]),
+            files(&[("/a", 100), ("/b", 20)]),
+            files(&[("/a", 200), ("/b", 20)]),
+        ];
+        assert_eq!(
+            progress(&data),
+            [
+                ("/a".to_owned(), vec![0, 500, 500, 600]),
+                ("/b".to_owned(), vec![0, 10, 20, 20]),
+            ]
+        );
+    }
+
+    #[test]
+    fn sched_rate() {
+        let sched = |faults: u64, run_delay: Option<u64>| Sched {
+            voluntary_ctxt_switches: 0,
+            nonvoluntary_ctxt_switches: 0,
+            minor_faults: faults,
+            major_faults: 0,
+            run_delay,
+            timeslices: None,
+        };
+        let rate =
+            sched(30, Some(5_000_000)).rate(&sched(10, Some(1_000_000)), Duration::from_secs(2));
+        assert_eq!(rate.minor_faults, 10.0);
+        // 纳秒转换为每秒的毫秒数
+        assert_eq!(rate.run_delay, Some(2_000.0));
+
+        let rate = sched(30, None).rate(&sched(10, None), Duration::from_secs(2));
+        assert_eq!(rate.run_delay, None);
+        // 计数器变小时为0
+        let rate = sched(10, None).rate(&sched(30, None), Duration::from_secs(2));
+        assert_eq!(rate.minor_faults, 0.0);
+    }
+}
@@ -1,286 +1,1709 @@
-use byte_unit::{Byte, ByteUnit};
-use plotters::prelude::{AsRelative, ChartBuilder, Color, IntoDrawingArea, LabelAreaPosition, LineSeries, RGBColor, Rectangle, SVGBackend, BLACK, BLUE, GREEN, MAGENTA, RED, WHITE, YELLOW, CYAN};
+use byte_unit::Byte;
+#[cfg(any(feature = "png", feature = "pdf"))]
+use once_cell::sync::Lazy;
+use plotters::coord::ranged1d::{AsRangedCoord, ValueFormatter};
+use plotters::coord::types::RangedCoordf64;
+use plotters::coord::Shift;
+use plotters::prelude::{AsRelative, Cartesian2d, ChartBuilder, ChartContext, Color, DrawingArea, IntoDrawingArea, IntoLogRange, LabelAreaPosition, LineSeries, PathElement, Ranged, Rectangle, SVGBackend, Text, TextStyle};
+use std::collections::{BTreeSet, HashMap};
+use std::ops::Range;
 use std::path::Path;
+use std::str::FromStr;
 
-use crate::data::Data;
+use crate::data::{
+    device_io, effective_limits, file_progress, Data, Fd, Host, Limits, Marker, PressureLine,
+    SchedRate,
+};
+use crate::plot::{Metric, Plot, Transform};
+use crate::theme::{Theme, Units};
 
-pub fn render_memory<P>(data: &[Data], output: P) -> anyhow::Result<()>
+// 图表的文件格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    #[default]
+    Svg,
+    // 需要png feature
+    Png,
+    // 需要pdf feature
+    Pdf,
+}
+
+impl Format {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Svg => "svg",
+            Format::Png => "png",
+            Format::Pdf => "pdf",
+        }
+    }
+
+    // 这次构建是否启用了这个格式的feature
+    pub fn supported(&self) -> bool {
+        match self {
+            Format::Svg => true,
+            Format::Png => cfg!(feature = "png"),
+            Format::Pdf => cfg!(feature = "pdf"),
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "svg" => Ok(Format::Svg),
+            "png" => Ok(Format::Png),
+            "pdf" => Ok(Format::Pdf),
+            _ => Err(format!("unknown format {}, expected svg, png or pdf", s)),
+        }
+    }
+}
+
+// 全部图表都先绘制为svg, 再转换为`format`
+#[cfg_attr(not(feature = "png"), allow(unused_variables))]
+fn save(svg: &str, size: (u32, u32), format: Format, output: &Path) -> anyhow::Result<()> {
+    match format {
+        Format::Svg => std::fs::write(output, svg)?,
+        #[cfg(feature = "png")]
+        Format::Png => {
+            let tree = resvg::Tree::from_usvg(&parse_svg(svg)?);
+            let mut pixmap = resvg::tiny_skia::Pixmap::new(size.0, size.1)
+                .ok_or_else(|| anyhow::anyhow!("invalid image size {:?}", size))?;
+            tree.render(resvg::tiny_skia::Transform::default(), &mut pixmap.as_mut());
+            pixmap.save_png(output)?;
+        }
+        #[cfg(feature = "pdf")]
+        Format::Pdf => std::fs::write(
+            output,
+            svg2pdf::convert_tree(&parse_svg(svg)?, svg2pdf::Options::default()),
+        )?,
+        #[allow(unreachable_patterns)]
+        format => anyhow::bail!("this build does not support {} output", format.extension()),
+    }
+    Ok(())
+}
+
+// 文字转换为路径, 使用系统字体
+#[cfg(any(feature = "png", feature = "pdf"))]
+fn parse_svg(svg: &str) -> anyhow::Result<usvg::Tree> {
+    use usvg::fontdb::{Database, Family, Query};
+    use usvg::{TreeParsing, TreeTextToPath};
+
+    static FONTS: Lazy<Database> = Lazy::new(|| {
+        let mut fonts = Database::new();
+        fonts.load_system_fonts();
+        // fontdb默认的sans-serif是Arial, 没有时使用其他常见的字体
+        let sans_serif = ["Arial", "DejaVu Sans", "Liberation Sans", "Noto Sans"]
+            .into_iter()
+            .find(|family| {
+                fonts
+                    .query(&Query {
+                        families: &[Family::Name(family)],
+                        ..Query::default()
+                    })
+                    .is_some()
+            });
+        if let Some(family) = sans_serif {
+            fonts.set_sans_serif_family(family);
+        }
+        fonts
+    });
+
+    let mut tree = usvg::Tree::from_str(svg, &usvg::Options::default())?;
+    tree.convert_text(&FONTS);
+    Ok(tree)
+}
+
+// 资源限制在每个采样时的值, 作为参考线绘制. 没有限制的采样跳过
+fn limit_series<'a, T, F>(
+    limits: &'a [Option<&Limits>],
+    f: F,
+) -> impl Iterator<Item = (usize, T)> + 'a
+where
+    F: Fn(&Limits) -> Option<T> + 'a,
+{
+    limits
+        .iter()
+        .enumerate()
+        .filter_map(move |(x, limits)| limits.and_then(&f).map(|limit| (x, limit)))
+}
+
+// 系统整体数据在每个采样时的值, 与进程的数据叠加绘制. 没有记录的采样跳过
+fn host_series<'a, T, F>(data: &'a [Data], f: F) -> impl Iterator<Item = (usize, T)> + 'a
+where
+    F: Fn(&Host) -> T + 'a,
+{
+    data.iter()
+        .enumerate()
+        .filter_map(move |(x, data)| data.host.as_ref().map(|host| (x, f(host))))
+}
+
+// 一个图表的y轴选项, 见`Chart::group`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Axes {
+    // 对数坐标
+    pub log: bool,
+    // 第一组曲线使用左侧的y轴, 其他组使用右侧的y轴
+    pub secondary: bool,
+    // 每组曲线绘制在单独的子图中
+    pub split: bool,
+}
+
+// 可以设置`Axes`的图表, 与输出的文件名相同. custom为全部的自定义指标
+pub const CHARTS: &[&str] = &[
+    "memory",
+    "cpu_time",
+    "cpu_usage",
+    "cpu_cores",
+    "io",
+    "io_syscalls",
+    "files",
+    "devices",
+    "sched",
+    "fd",
+    "sockets",
+    "custom",
+    "pressure",
+    "stall_time",
+    "plot",
+];
+
+// 坐标轴上的值的单位
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Unit {
+    // 按最大值选择B, KiB, MiB, GiB...或者B, KB, MB, GB...
+    Bytes,
+    // 按最大值选择µs, ms, s
+    Micros,
+    // 不换算
+    Fixed(String),
+    // 每秒的变化量
+    PerSecond(Box<Unit>),
+}
+
+impl Unit {
+    fn scale(&self, max: f64, units: Units) -> Scale {
+        match self {
+            Unit::Bytes => {
+                let unit = Byte::from(max as u64)
+                    .get_appropriate_unit(units == Units::Binary)
+                    .get_unit();
+                Scale {
+                    factor: unit.get_unit_bytes() as f64,
+                    unit: unit.as_str().to_owned(),
+                }
+            }
+            Unit::Micros => {
+                let (factor, unit) = if max >= 1_000_000.0 {
+                    (1_000_000.0, "s")
+                } else if max >= 1_000.0 {
+                    (1_000.0, "ms")
+                } else {
+                    (1.0, "µs")
+                };
+                Scale {
+                    factor,
+                    unit: unit.to_owned(),
+                }
+            }
+            Unit::Fixed(unit) => Scale {
+                factor: 1.0,
+                unit: unit.clone(),
+            },
+            Unit::PerSecond(unit) => {
+                let scale = unit.scale(max, units);
+                Scale {
+                    unit: format!("{}/s", scale.unit),
+                    ..scale
+                }
+            }
+        }
+    }
+}
+
+// 换算后的单位, 值除以`factor`后显示
+#[derive(Debug, Clone)]
+struct Scale {
+    factor: f64,
+    unit: String,
+}
+
+// 曲线的种类, 决定线的粗细和透明度
+#[derive(Debug, Clone, Copy)]
+enum Line {
+    // 进程的数据
+    Data,
+    // 系统整体的数据, 半透明且更细
+    Host,
+    // 资源限制, 最细的参考线
+    Limit,
+}
+
+// 一条曲线, 值是换算单位之前的
+struct Series {
+    label: String,
+    // 在调色板中的序号
+    color: usize,
+    line: Line,
+    points: Vec<(usize, f64)>,
+}
+
+// 一组单位相同的曲线
+struct Group {
+    unit: Unit,
+    series: Vec<Series>,
+}
+
+// x轴. 全部采样都有时间时为从第一个采样开始的秒数, 否则(旧文件)为采样的序号
+struct XAxis {
+    xs: Vec<f64>,
+    desc: &'static str,
+}
+
+impl XAxis {
+    fn new(data: &[Data]) -> XAxis {
+        let times = data
+            .iter()
+            .map(|data| data.time)
+            .collect::<Option<Vec<_>>>();
+        match times {
+            Some(times) if !times.is_empty() => XAxis {
+                xs: times
+                    .iter()
+                    .map(|time| time.saturating_sub(times[0]) as f64 / 1000.0)
+                    .collect(),
+                desc: "s",
+            },
+            _ => XAxis {
+                xs: (0..data.len()).map(|x| x as f64).collect(),
+                desc: "",
+            },
+        }
+    }
+
+    // 第`i`个采样的位置, 最后一个采样之后的标记绘制在最后
+    fn at(&self, i: usize) -> f64 {
+        self.xs
+            .get(i)
+            .or_else(|| self.xs.last())
+            .copied()
+            .unwrap_or(0.0)
+    }
+
+    fn range(&self) -> Range<f64> {
+        let end = match self.desc {
+            "s" => self.xs.last().copied().unwrap_or(0.0),
+            _ => self.xs.len() as f64,
+        };
+        0.0..end.max(1.0)
+    }
+}
+
+// 一个图表. 曲线按添加的顺序从调色板取颜色, 绘制时才根据全部的值决定y轴的范围和单位
+struct Chart {
+    caption: String,
+    x: XAxis,
+    groups: Vec<Group>,
+    next: usize,
+}
+
+impl Chart {
+    fn new(caption: impl Into<String>, data: &[Data], unit: Unit) -> Chart {
+        Chart {
+            caption: caption.into(),
+            x: XAxis::new(data),
+            groups: vec![Group {
+                unit,
+                series: Vec::new(),
+            }],
+            next: 0,
+        }
+    }
+
+    // 之后添加的曲线属于新的一组, 可以绘制在右侧的y轴或者单独的子图中
+    fn group(&mut self, unit: Unit) {
+        self.groups.push(Group {
+            unit,
+            series: Vec::new(),
+        });
+    }
+
+    fn next_color(&mut self) -> usize {
+        self.next += 1;
+        self.next - 1
+    }
+
+    fn data<I>(&mut self, label: impl Into<String>, points: I)
+    where
+        I: IntoIterator<Item = (usize, f64)>,
+    {
+        let color = self.next_color();
+        self.series(label, color, Line::Data, points);
+    }
+
+    fn host<I>(&mut self, label: impl Into<String>, points: I)
+    where
+        I: IntoIterator<Item = (usize, f64)>,
+    {
+        let color = self.next_color();
+        self.series(label, color, Line::Host, points);
+    }
+
+    fn limit<I>(&mut self, label: impl Into<String>, points: I)
+    where
+        I: IntoIterator<Item = (usize, f64)>,
+    {
+        let color = self.next_color();
+        self.series(label, color, Line::Limit, points);
+    }
+
+    // 没有点的曲线不绘制, 也不出现在图例中
+    fn series<I>(&mut self, label: impl Into<String>, color: usize, line: Line, points: I)
+    where
+        I: IntoIterator<Item = (usize, f64)>,
+    {
+        let points = points.into_iter().collect::<Vec<_>>();
+        if points.is_empty() {
+            return;
+        }
+        if let Some(group) = self.groups.last_mut() {
+            group.series.push(Series {
+                label: label.into(),
+                color,
+                line,
+                points,
+            });
+        }
+    }
+
+    // 每个子图左侧和右侧的y轴上的组. 单位不同的组总是绘制在不同的子图中
+    fn layout(&self, axes: Axes) -> Vec<(Vec<usize>, Vec<usize>)> {
+        let used = (0..self.groups.len())
+            .filter(|i| !self.groups[*i].series.is_empty())
+            .collect::<Vec<_>>();
+        let same_unit = |groups: &[usize]| {
+            groups
+                .windows(2)
+                .all(|w| self.groups[w[0]].unit == self.groups[w[1]].unit)
+        };
+
+        if used.is_empty() {
+            // 没有数据也绘制空的坐标轴
+            vec![(vec![0], vec![])]
+        } else if axes.split || (!axes.secondary && !same_unit(&used)) {
+            used.into_iter().map(|i| (vec![i], vec![])).collect()
+        } else if axes.secondary && used.len() > 1 && same_unit(&used[1..]) {
+            vec![(vec![used[0]], used[1..].to_vec())]
+        } else if axes.secondary && used.len() > 1 {
+            used.into_iter().map(|i| (vec![i], vec![])).collect()
+        } else {
+            vec![(used, vec![])]
+        }
+    }
+
+    // 背景, 标题, 每个子图的坐标轴, 曲线, 标记和图例, 最后保存为`format`
+    fn draw(
+        &self,
+        theme: &Theme,
+        axes: Axes,
+        markers: &[(usize, Marker)],
+        format: Format,
+        output: &Path,
+    ) -> anyhow::Result<()> {
+        let panels = self.layout(axes);
+
+        let mut svg = String::new();
+        let root = SVGBackend::with_string(&mut svg, theme.size).into_drawing_area();
+
+        root.fill(&theme.background)?;
+
+        let title = TextStyle::from((theme.font.as_str(), theme.size.1 as f64 * 0.05))
+            .color(&theme.foreground);
+        let areas = root
+            .titled(&self.caption, title)?
+            .split_evenly((panels.len(), 1));
+
+        for (i, ((left, right), area)) in panels.iter().zip(areas).enumerate() {
+            let left = Axis::new(&self.groups, left, theme.units, axes.log);
+            let right =
+                (!right.is_empty()).then(|| Axis::new(&self.groups, right, theme.units, axes.log));
+            // 标记的标签只在第一个子图中绘制
+            let panel = Panel {
+                area: &area,
+                theme,
+                x: &self.x,
+                markers,
+                marker_labels: i == 0,
+            };
+            if axes.log {
+                panel.draw(
+                    (&left, left.range.clone().log_scale()),
+                    right
+                        .as_ref()
+                        .map(|right| (right, right.range.clone().log_scale())),
+                )?;
+            } else {
+                panel.draw(
+                    (&left, left.range.clone()),
+                    right.as_ref().map(|right| (right, right.range.clone())),
+                )?;
+            }
+        }
+
+        root.present()?;
+
+        drop(root);
+
+        save(&svg, theme.size, format, output)
+    }
+}
+
+// 一个y轴, 以及绘制在它上面的曲线
+struct Axis<'a> {
+    series: Vec<&'a Series>,
+    scale: Scale,
+    // 换算单位之后的范围
+    range: Range<f64>,
+    log: bool,
+}
+
+impl<'a> Axis<'a> {
+    fn new(groups: &'a [Group], members: &[usize], units: Units, log: bool) -> Axis<'a> {
+        let series = members
+            .iter()
+            .flat_map(|i| &groups[*i].series)
+            .collect::<Vec<_>>();
+        let values = || {
+            series
+                .iter()
+                .flat_map(|series| series.points.iter().map(|(_, value)| *value))
+        };
+
+        let max = values().fold(0f64, f64::max);
+        let scale = groups[members[0]].unit.scale(max, units);
+        let max = max / scale.factor;
+        let range = if log {
+            // 对数坐标从最小的正数开始, 0和负数绘制在底部
+            let min = values()
+                .filter(|value| *value > 0.0)
+                .fold(f64::INFINITY, f64::min)
+                / scale.factor;
+            if min.is_finite() {
+                // 扩展到整数的数量级, 否则不足一个数量级时没有刻度
+                let min = 10f64.powf(min.log10().floor());
+                min..10f64.powf(max.log10().ceil()).max(min * 10.0)
+            } else {
+                1.0..10.0
+            }
+        } else {
+            let min = values().fold(0f64, f64::min) / scale.factor;
+            // 全部为0时也绘制刻度
+            min..if max > min { max } else { min + 1.0 }
+        };
+
+        Axis {
+            series,
+            scale,
+            range,
+            log,
+        }
+    }
+
+    fn value(&self, value: f64) -> f64 {
+        let value = value / self.scale.factor;
+        if self.log {
+            value.max(self.range.start)
+        } else {
+            value
+        }
+    }
+
+    fn desc(&self) -> &str {
+        &self.scale.unit
+    }
+}
+
+// 一个子图
+struct Panel<'a, 'b> {
+    area: &'a DrawingArea<SVGBackend<'b>, Shift>,
+    theme: &'a Theme,
+    x: &'a XAxis,
+    markers: &'a [(usize, Marker)],
+    marker_labels: bool,
+}
+
+impl Panel<'_, '_> {
+    // `left`和`right`是y轴和它的坐标范围, 线性或者对数
+    fn draw<Y>(&self, left: (&Axis, Y), right: Option<(&Axis, Y)>) -> anyhow::Result<()>
+    where
+        Y: AsRangedCoord<Value = f64> + Clone,
+        Y::CoordDescType: ValueFormatter<f64>,
+    {
+        let theme = self.theme;
+        let font = theme.font.as_str();
+        let foreground = &theme.foreground;
+        let label_style = || (font, (12).percent().max(12), foreground);
+
+        // 固定的像素, 使多个子图的坐标轴对齐
+        let mut builder = ChartBuilder::on(self.area);
+        builder
+            .set_label_area_size(LabelAreaPosition::Left, theme.size.0 * 8 / 100)
+            .set_label_area_size(LabelAreaPosition::Bottom, theme.size.1 * 4 / 100)
+            .margin((1).percent());
+        if right.is_some() {
+            builder.set_label_area_size(LabelAreaPosition::Right, theme.size.0 * 8 / 100);
+        }
+        let (left, left_range) = left;
+        let mut chart = builder
+            .build_cartesian_2d(self.x.range(), left_range.clone())?
+            .set_secondary_coord(
+                self.x.range(),
+                right
+                    .as_ref()
+                    .map(|(_, range)| range.clone())
+                    .unwrap_or(left_range),
+            );
+
+        let mut mesh = chart.configure_mesh();
+        if !left.desc().is_empty() {
+            mesh.y_desc(left.desc());
+        }
+        if !self.x.desc.is_empty() {
+            mesh.x_desc(self.x.desc);
+        }
+        mesh.axis_style(foreground)
+            .bold_line_style(foreground.mix(0.2))
+            .light_line_style(foreground.mix(0.1))
+            .label_style(label_style())
+            .draw()?;
+
+        if let Some((right, _)) = &right {
+            chart
+                .configure_secondary_axes()
+                .y_desc(right.desc())
+                .axis_style(foreground)
+                .label_style(label_style())
+                .draw()?;
+        }
+
+        let lines = left
+            .series
+            .iter()
+            .map(|series| (*series, left, false))
+            .chain(right.iter().flat_map(|(right, _)| {
+                right
+                    .series
+                    .iter()
+                    .map(move |series| (*series, *right, true))
+            }));
+        for (series, axis, secondary) in lines {
+            let color = theme.palette[series.color % theme.palette.len()];
+            let width = theme.line_width.max(1);
+            let (style, half_height) = match series.line {
+                Line::Data => (color.stroke_width(width), 5),
+                Line::Host => (color.mix(0.5).stroke_width((width * 2 / 3).max(1)), 5),
+                Line::Limit => (color.stroke_width(1), 1),
+            };
+            let points = series
+                .points
+                .iter()
+                .map(|(x, value)| (self.x.at(*x), axis.value(*value)));
+            let anno = if secondary {
+                chart.draw_secondary_series(LineSeries::new(points, style.clone()))?
+            } else {
+                chart.draw_series(LineSeries::new(points, style.clone()))?
+            };
+            anno.label(if secondary {
+                format!("{} (right)", series.label)
+            } else {
+                series.label.clone()
+            })
+            .legend(move |(x, y)| {
+                Rectangle::new(
+                    [(x, y - half_height), (x + 10, y + half_height)],
+                    style.filled(),
+                )
+            });
+        }
+
+        draw_markers(&mut chart, theme, self.x, self.markers, self.marker_labels)?;
+
+        chart
+            .configure_series_labels()
+            .border_style(foreground)
+            .label_font((font, 12, foreground))
+            .draw()?;
+
+        Ok(())
+    }
+}
+
+// 标记绘制为贯穿整个图表的竖线, 标签在顶部. `usize`是标记之后的第一个采样
+fn draw_markers<Y>(
+    chart: &mut ChartContext<SVGBackend, Cartesian2d<RangedCoordf64, Y>>,
+    theme: &Theme,
+    x: &XAxis,
+    markers: &[(usize, Marker)],
+    labels: bool,
+) -> anyhow::Result<()>
+where
+    Y: Ranged,
+    Y::ValueType: Clone + 'static,
+{
+    let y_range = chart.y_range();
+    for (i, marker) in markers {
+        let x = x.at(*i);
+        chart.draw_series([PathElement::new(
+            vec![(x, y_range.start.clone()), (x, y_range.end.clone())],
+            theme.foreground.mix(0.6).stroke_width(2),
+        )])?;
+        if labels {
+            chart.draw_series([Text::new(
+                marker.label.clone(),
+                (x, y_range.end.clone()),
+                TextStyle::from((theme.font.as_str(), 20)).color(&theme.foreground),
+            )])?;
+        }
+    }
+    Ok(())
+}
+
+// 进程内存, 虚拟内存和RLIMIT_AS为第二组
+pub fn render_memory<P>(
+    data: &[Data],
+    markers: &[(usize, Marker)],
+    theme: &Theme,
+    axes: Axes,
+    format: Format,
+    output: P,
+) -> anyhow::Result<()>
 where
     P: AsRef<Path>,
 {
-    let to_mb = |b: u64| Byte::from(b).get_adjusted_unit(ByteUnit::MB).get_value() as u64;
+    let limits = effective_limits(data);
+    // 最接近memory.max的时候
+    let peak_ratio = data
+        .iter()
+        .zip(&limits)
+        .filter_map(|(data, limits)| {
+            limits
+                .and_then(|l| l.memory_max)
+                .map(|max| data.memory.rss as f64 / max as f64)
+        })
+        .fold(None, |peak: Option<f64>, ratio| {
+            Some(peak.map_or(ratio, |peak| peak.max(ratio)))
+        });
+    let memory_max = match peak_ratio {
+        Some(ratio) => format!("memory.max (peak rss {:.0}%)", ratio * 100.0),
+        None => "memory.max".to_owned(),
+    };
 
-    let x_len = data.len();
-    let y_len = [
-        data.iter()
-            .map(|data| data.memory.vms)
-            .map(to_mb)
-            .max()
-            .unwrap_or(0),
-        data.iter()
-            .map(|data| data.memory.rss)
-            .map(to_mb)
-            .max()
-            .unwrap_or(0),
-        data.iter()
-            .filter_map(|data| data.memory.data)
-            .map(to_mb)
-            .max()
-            .unwrap_or(0),
-        data.iter()
-            .filter_map(|data| data.memory.text)
-            .map(to_mb)
-            .max()
-            .unwrap_or(0),
-        data.iter()
-            .filter_map(|data| data.memory.shared)
-            .map(to_mb)
-            .max()
-            .unwrap_or(0),
-    ]
-    .iter()
-    .max()
-    .cloned()
-    .unwrap_or(0);
-    let root = SVGBackend::new(&output, (1920, 1080)).into_drawing_area();
-
-    root.fill(&WHITE)?;
-
-    let mut chart = ChartBuilder::on(&root)
-        .set_label_area_size(LabelAreaPosition::Left, (8).percent())
-        .set_label_area_size(LabelAreaPosition::Bottom, (4).percent())
-        .caption("Memory Usage", ("sans-serif", (5).percent_height()))
-        .margin((1).percent())
-        .build_cartesian_2d(0..x_len, 0..y_len)?;
-
-    chart.configure_mesh().y_desc("MB").draw()?;
-
-    let elems: Vec<(&'static str, RGBColor, Box<dyn Fn(&Data) -> u64>)> = vec![
-        ("vms", GREEN, box |d: &Data| d.memory.vms),
-        ("rss", RED, box |d: &Data| d.memory.rss),
-        ("shared", YELLOW, box |d: &Data| {
-            d.memory.shared.unwrap_or(0)
-        }),
-        ("text", BLUE, box |d: &Data| d.memory.text.unwrap_or(0)),
-        ("data", MAGENTA, box |d: &Data| d.memory.data.unwrap_or(0)),
+    let cgroup = data.iter().any(|d| d.cgroup.is_some());
+    let elems: Vec<(&'static str, Box<dyn Fn(&Data) -> u64>)> = if cgroup {
+        let memory_stat = |key: &'static str| {
+            move |d: &Data| {
+                d.cgroup
+                    .as_ref()
+                    .and_then(|cgroup| cgroup.memory_stat.get(key))
+                    .copied()
+                    .unwrap_or(0)
+            }
+        };
+        vec![
+            ("memory.current", Box::new(|d: &Data| d.memory.rss)),
+            ("anon", Box::new(memory_stat("anon"))),
+            ("file", Box::new(memory_stat("file"))),
+            ("kernel", Box::new(memory_stat("kernel"))),
+            ("shmem", Box::new(memory_stat("shmem"))),
+            ("sock", Box::new(memory_stat("sock"))),
+        ]
+    } else {
+        vec![
+            ("rss", Box::new(|d: &Data| d.memory.rss)),
+            ("shared", Box::new(|d: &Data| d.memory.shared.unwrap_or(0))),
+            ("text", Box::new(|d: &Data| d.memory.text.unwrap_or(0))),
+            ("data", Box::new(|d: &Data| d.memory.data.unwrap_or(0))),
+        ]
+    };
+    let host_elems: Vec<(&'static str, Box<dyn Fn(&Host) -> u64>)> = vec![
+        (
+            "host used",
+            Box::new(|h: &Host| h.memory_total.saturating_sub(h.memory_available)),
+        ),
+        ("host swap used", Box::new(|h: &Host| h.swap_used)),
+    ];
+    let limit_elems: Vec<(String, Box<dyn Fn(&Limits) -> Option<u64>>)> = vec![
+        (memory_max, Box::new(|l: &Limits| l.memory_max)),
+        ("RLIMIT_DATA".to_owned(), Box::new(|l: &Limits| l.data)),
     ];
 
-    for (label, color, elem) in elems {
-        chart
-            .draw_series(LineSeries::new(
-                data.iter()
-                    .enumerate()
-                    .map(|(x, data)| (x, to_mb(elem(data)))),
-                color.stroke_width(3),
-            ))?
-            .label(label)
-            .legend(move |(x, y)| Rectangle::new([(x, y - 5), (x + 10, y + 5)], color.filled()));
+    let mut chart = Chart::new("Memory Usage", data, Unit::Bytes);
+    for (label, elem) in elems {
+        chart.data(
+            label,
+            data.iter()
+                .enumerate()
+                .map(|(x, data)| (x, elem(data) as f64)),
+        );
+    }
+    for (label, elem) in host_elems {
+        chart.host(
+            label,
+            host_series(data, elem).map(|(x, value)| (x, value as f64)),
+        );
+    }
+    for (label, elem) in limit_elems {
+        chart.limit(
+            label,
+            limit_series(&limits, elem).map(|(x, limit)| (x, limit as f64)),
+        );
+    }
+
+    if !cgroup {
+        chart.group(Unit::Bytes);
+        chart.data(
+            "vms",
+            data.iter()
+                .enumerate()
+                .map(|(x, data)| (x, data.memory.vms as f64)),
+        );
+        chart.limit(
+            "RLIMIT_AS",
+            limit_series(&limits, |l: &Limits| l.address_space).map(|(x, limit)| (x, limit as f64)),
+        );
     }
 
-    chart.configure_series_labels().border_style(BLACK).draw()?;
+    chart.draw(theme, axes, markers, format, output.as_ref())
+}
 
-    root.present()?;
+pub fn render_cpu_time<P>(
+    data: &[Data],
+    markers: &[(usize, Marker)],
+    theme: &Theme,
+    axes: Axes,
+    format: Format,
+    output: P,
+) -> anyhow::Result<()>
+where
+    P: AsRef<Path>,
+{
+    let elems: Vec<(&'static str, Box<dyn Fn(&Data) -> f64>)> = vec![
+        ("system", Box::new(|d: &Data| d.cpu_time.system)),
+        ("user", Box::new(|d: &Data| d.cpu_time.user)),
+    ];
 
-    drop(chart);
-    drop(root);
+    let mut chart = Chart::new("Cpu Time", data, Unit::Micros);
+    for (label, elem) in elems {
+        chart.data(
+            label,
+            data.iter().enumerate().map(|(x, data)| (x, elem(data))),
+        );
+    }
 
-    Ok(())
+    chart.draw(theme, axes, markers, format, output.as_ref())
 }
 
-pub fn render_cpu_time<P>(data: &[Data], output: P) -> anyhow::Result<()>
+// `normalise`为记录时机器的核数, 这时100%为全部核满载, 否则100%为一个核满载
+pub fn render_cpu_usage<P>(
+    data: &[Data],
+    markers: &[(usize, Marker)],
+    normalise: Option<u32>,
+    theme: &Theme,
+    axes: Axes,
+    format: Format,
+    output: P,
+) -> anyhow::Result<()>
 where
     P: AsRef<Path>,
 {
-    let x_len = data.len();
-    let y_len = [
+    let scale = 1.0 / normalise.unwrap_or(1).max(1) as f64;
+
+    let limits = effective_limits(data);
+    let cpu_max = |l: &Limits| l.cpu_max.map(|max| max * scale);
+    // 允许运行的核全部满载
+    let affinity = |d: &Data| {
+        d.cores
+            .as_ref()
+            .map(|cores| cores.affinity.len() as f64 * 100.0 * scale)
+    };
+
+    let host_elems: Vec<(&'static str, Box<dyn Fn(&Host) -> f64>)> = vec![
+        (
+            "host usage",
+            Box::new(move |h: &Host| h.cpu_usage as f64 * scale),
+        ),
+        // 负载为1时相当于一个核满载
+        (
+            "host load1 (x100%)",
+            Box::new(move |h: &Host| {
+                h.load_average
+                    .map(|load| load[0] * 100.0 * scale)
+                    .unwrap_or(0.0)
+            }),
+        ),
+    ];
+
+    let unit = match normalise {
+        Some(cores) => format!("% of {} cores", cores),
+        None => "% (100% = 1 core)".to_owned(),
+    };
+    let mut chart = Chart::new("Cpu Usage", data, Unit::Fixed(unit));
+    chart.data(
+        "usage",
         data.iter()
-            .map(|data| data.cpu_time.system as u64)
-            .max()
-            .unwrap_or(0),
+            .enumerate()
+            .map(|(x, data)| (x, data.cpu_usage.0 as f64 * scale)),
+    );
+    for (label, elem) in host_elems {
+        chart.host(label, host_series(data, elem));
+    }
+    chart.limit("cpu.max", limit_series(&limits, cpu_max));
+    chart.limit(
+        "affinity",
         data.iter()
-            .map(|data| data.cpu_time.user as u64)
-            .max()
-            .unwrap_or(0),
-    ]
-    .iter()
-    .max()
-    .cloned()
-    .unwrap_or(0);
-    let root = SVGBackend::new(&output, (1920, 1080)).into_drawing_area();
-
-    root.fill(&WHITE)?;
-
-    let mut chart = ChartBuilder::on(&root)
-        .set_label_area_size(LabelAreaPosition::Left, (8).percent())
-        .set_label_area_size(LabelAreaPosition::Bottom, (4).percent())
-        .caption("Cpu Time", ("sans-serif", (5).percent_height()))
-        .margin((1).percent())
-        .build_cartesian_2d(0..x_len, 0..y_len)?;
-
-    chart.configure_mesh().y_desc("us").draw()?;
-
-    let elems: Vec<(&'static str, RGBColor, Box<dyn Fn(&Data) -> u64>)> = vec![
-        ("system", GREEN, box |d: &Data| d.cpu_time.system as u64),
-        ("user", RED, box |d: &Data| d.cpu_time.user as u64),
+            .enumerate()
+            .filter_map(|(x, data)| affinity(data).map(|limit| (x, limit))),
+    );
+
+    chart.draw(theme, axes, markers, format, output.as_ref())
+}
+
+// 进程在每个核上的使用率, `cores`为记录时机器的核数
+pub fn render_cpu_cores<P>(
+    data: &[Data],
+    markers: &[(usize, Marker)],
+    cores: u32,
+    theme: &Theme,
+    axes: Axes,
+    format: Format,
+    output: P,
+) -> anyhow::Result<()>
+where
+    P: AsRef<Path>,
+{
+    let usage = |data: &Data, cpu: u32| {
+        data.cores
+            .as_ref()
+            .and_then(|cores| cores.usage.get(&cpu))
+            .copied()
+            .unwrap_or(0f32) as f64
+    };
+
+    // 只绘制进程运行过的核
+    let used = data
+        .iter()
+        .filter_map(|data| data.cores.as_ref())
+        .flat_map(|cores| cores.usage.iter())
+        .filter(|(_, usage)| **usage > 0.0)
+        .map(|(cpu, _)| *cpu)
+        .filter(|cpu| *cpu < cores)
+        .collect::<BTreeSet<_>>();
+
+    // 系统整体在这个核上的使用率
+    let host_usage = |cpu: u32| {
+        move |host: &Host| {
+            host.cpu_usage_per_core
+                .get(cpu as usize)
+                .copied()
+                .unwrap_or(0f32) as f64
+        }
+    };
+
+    let mut chart = Chart::new("Cpu Usage Per Core", data, Unit::Fixed("%".to_owned()));
+    for cpu in used {
+        // 同一个核在不同的记录中使用相同的颜色, 系统整体的使用率用同一颜色的半透明的线
+        chart.series(
+            format!("cpu{}", cpu),
+            cpu as usize,
+            Line::Data,
+            data.iter()
+                .enumerate()
+                .map(|(x, data)| (x, usage(data, cpu))),
+        );
+        chart.series(
+            format!("host cpu{}", cpu),
+            cpu as usize,
+            Line::Host,
+            host_series(data, host_usage(cpu)),
+        );
+    }
+
+    chart.draw(theme, axes, markers, format, output.as_ref())
+}
+
+// 实际读写存储设备的字节, 经过读写系统调用的字节为第二组
+pub fn render_io<P>(
+    data: &[Data],
+    markers: &[(usize, Marker)],
+    theme: &Theme,
+    axes: Axes,
+    format: Format,
+    output: P,
+) -> anyhow::Result<()>
+where
+    P: AsRef<Path>,
+{
+    let elems: Vec<(&'static str, Box<dyn Fn(&Data) -> u64>)> = vec![
+        ("storage written", Box::new(|d: &Data| d.io.storage_written)),
+        ("storage read", Box::new(|d: &Data| d.io.storage_read)),
+    ];
+    // 只有linux才有的字段
+    let chars_elems: Vec<(&'static str, Box<dyn Fn(&Data) -> Option<u64>>)> = vec![
+        (
+            "chars written (incl. pipes, sockets, cache)",
+            Box::new(|d: &Data| d.io.chars_written),
+        ),
+        (
+            "chars read (incl. pipes, sockets, cache)",
+            Box::new(|d: &Data| d.io.chars_read),
+        ),
+        (
+            "cancelled write",
+            Box::new(|d: &Data| d.io.cancelled_write_bytes),
+        ),
     ];
 
-    for (label, color, elem) in elems {
-        chart
-            .draw_series(LineSeries::new(
-                data.iter().enumerate().map(|(x, data)| (x, elem(data))),
-                color.stroke_width(3),
-            ))?
-            .label(label)
-            .legend(move |(x, y)| Rectangle::new([(x, y - 5), (x + 10, y + 5)], color.filled()));
+    // 系统的磁盘和网络读写是开机以来的累计值, 从记录开始时计算
+    let first = data.iter().find_map(|data| data.host.as_ref());
+    let (first_read, first_written) = first
+        .map(|host| (host.disk_read, host.disk_written))
+        .unwrap_or_default();
+    let (first_recv, first_sent) = first
+        .map(|host| (host.net_recv, host.net_sent))
+        .unwrap_or_default();
+    let host_elems: Vec<(&'static str, Box<dyn Fn(&Host) -> u64>)> = vec![
+        (
+            "host disk_written",
+            Box::new(move |h: &Host| h.disk_written.saturating_sub(first_written)),
+        ),
+        (
+            "host disk_read",
+            Box::new(move |h: &Host| h.disk_read.saturating_sub(first_read)),
+        ),
+        (
+            "host net_sent",
+            Box::new(move |h: &Host| h.net_sent.saturating_sub(first_sent)),
+        ),
+        (
+            "host net_recv",
+            Box::new(move |h: &Host| h.net_recv.saturating_sub(first_recv)),
+        ),
+    ];
+
+    let mut chart = Chart::new("I/O", data, Unit::Bytes);
+    for (label, elem) in elems {
+        chart.data(
+            label,
+            data.iter()
+                .enumerate()
+                .map(|(x, data)| (x, elem(data) as f64)),
+        );
+    }
+    for (label, elem) in host_elems {
+        chart.host(
+            label,
+            host_series(data, elem).map(|(x, value)| (x, value as f64)),
+        );
     }
 
-    chart.configure_series_labels().border_style(BLACK).draw()?;
+    chart.group(Unit::Bytes);
+    for (label, elem) in chars_elems {
+        chart.data(
+            label,
+            data.iter()
+                .enumerate()
+                .filter_map(|(x, data)| Some((x, elem(data)? as f64))),
+        );
+    }
 
-    root.present()?;
+    chart.draw(theme, axes, markers, format, output.as_ref())
+}
 
-    drop(chart);
-    drop(root);
+// 读写系统调用的次数, linux only
+pub fn render_io_syscalls<P>(
+    data: &[Data],
+    markers: &[(usize, Marker)],
+    theme: &Theme,
+    axes: Axes,
+    format: Format,
+    output: P,
+) -> anyhow::Result<()>
+where
+    P: AsRef<Path>,
+{
+    let elems: Vec<(&'static str, Box<dyn Fn(&Data) -> u64>)> = vec![
+        (
+            "write syscalls",
+            Box::new(|d: &Data| d.io.syscall_written.unwrap_or_default()),
+        ),
+        (
+            "read syscalls",
+            Box::new(|d: &Data| d.io.syscall_read.unwrap_or_default()),
+        ),
+    ];
 
-    Ok(())
+    let mut chart = Chart::new("I/O Syscalls", data, Unit::Fixed("count".to_owned()));
+    for (label, elem) in elems {
+        chart.data(
+            label,
+            data.iter()
+                .enumerate()
+                .map(|(x, data)| (x, elem(data) as f64)),
+        );
+    }
+
+    chart.draw(theme, axes, markers, format, output.as_ref())
 }
 
-pub fn render_cpu_usage<P>(data: &[Data], output: P) -> anyhow::Result<()>
+// 上下文切换和缺页, 运行队列的等待时间为第二组
+pub fn render_sched<P>(
+    data: &[Data],
+    markers: &[(usize, Marker)],
+    theme: &Theme,
+    axes: Axes,
+    format: Format,
+    output: P,
+) -> anyhow::Result<()>
 where
     P: AsRef<Path>,
 {
-    let x_len = data.len();
-    let y_len = data
-        .iter()
-        .map(|data| data.cpu_usage.0.round() as u64)
-        .max()
-        .unwrap_or(0);
-    let root = SVGBackend::new(&output, (1920, 1080)).into_drawing_area();
+    // 累计值转换为每秒的变化量, 第一个采样没有上一次采样, 视为0
+    let rates = std::iter::once(SchedRate::default())
+        .chain(data.windows(2).map(|w| {
+            match (&w[0].sched, &w[1].sched, w[1].elapsed_since(&w[0])) {
+                (Some(prev), Some(now), Some(elapsed)) => now.rate(prev, elapsed),
+                _ => SchedRate::default(),
+            }
+        }))
+        .take(data.len())
+        .collect::<Vec<_>>();
 
-    root.fill(&WHITE)?;
+    let elems: Vec<(&'static str, Box<dyn Fn(&SchedRate) -> f64>)> = vec![
+        (
+            "voluntary_ctxt_switches",
+            Box::new(|r: &SchedRate| r.voluntary_ctxt_switches),
+        ),
+        (
+            "nonvoluntary_ctxt_switches",
+            Box::new(|r: &SchedRate| r.nonvoluntary_ctxt_switches),
+        ),
+        ("minor_faults", Box::new(|r: &SchedRate| r.minor_faults)),
+        ("major_faults", Box::new(|r: &SchedRate| r.major_faults)),
+    ];
 
-    let mut chart = ChartBuilder::on(&root)
-        .set_label_area_size(LabelAreaPosition::Left, (8).percent())
-        .set_label_area_size(LabelAreaPosition::Bottom, (4).percent())
-        .caption("Cpu Usage", ("sans-serif", (5).percent_height()))
-        .margin((1).percent())
-        .build_cartesian_2d(0..x_len, 0..y_len)?;
+    let mut chart = Chart::new("Scheduling", data, Unit::Fixed("/s".to_owned()));
+    for (label, elem) in elems {
+        chart.data(
+            label,
+            rates.iter().enumerate().map(|(x, rate)| (x, elem(rate))),
+        );
+    }
 
-    chart.configure_mesh().y_desc("%").draw()?;
+    // 内核没有开启schedstat时没有run_delay
+    if rates.iter().any(|rate| rate.run_delay.is_some()) {
+        chart.group(Unit::PerSecond(Box::new(Unit::Micros)));
+        chart.data(
+            "run_delay",
+            rates
+                .iter()
+                .enumerate()
+                .filter_map(|(x, rate)| Some((x, rate.run_delay?))),
+        );
+    }
 
-    let elems: Vec<(&'static str, RGBColor, Box<dyn Fn(&Data) -> u64>)> =
-        vec![("usage", GREEN, box |d: &Data| d.cpu_usage.0.ceil() as u64)];
+    chart.draw(theme, axes, markers, format, output.as_ref())
+}
 
-    for (label, color, elem) in elems {
-        chart
-            .draw_series(LineSeries::new(
-                data.iter().enumerate().map(|(x, data)| (x, elem(data))),
-                color.stroke_width(3),
-            ))?
-            .label(label)
-            .legend(move |(x, y)| Rectangle::new([(x, y - 5), (x + 10, y + 5)], color.filled()));
+// 文件描述符, RLIMIT_NOFILE为第二组
+pub fn render_fd<P>(
+    data: &[Data],
+    markers: &[(usize, Marker)],
+    theme: &Theme,
+    axes: Axes,
+    format: Format,
+    output: P,
+) -> anyhow::Result<()>
+where
+    P: AsRef<Path>,
+{
+    let limits = effective_limits(data);
+
+    let elems: Vec<(&'static str, Box<dyn Fn(&Fd) -> u64>)> = vec![
+        ("total", Box::new(|fd: &Fd| fd.total)),
+        ("file", Box::new(|fd: &Fd| fd.file)),
+        ("socket", Box::new(|fd: &Fd| fd.socket)),
+        ("pipe", Box::new(|fd: &Fd| fd.pipe)),
+        ("anon_inode", Box::new(|fd: &Fd| fd.anon_inode)),
+        ("eventfd", Box::new(|fd: &Fd| fd.eventfd)),
+    ];
+
+    let mut chart = Chart::new("File Descriptors", data, Unit::Fixed("count".to_owned()));
+    for (label, elem) in elems {
+        chart.data(
+            label,
+            data.iter()
+                .enumerate()
+                .map(|(x, data)| (x, data.fd.as_ref().map(&elem).unwrap_or(0) as f64)),
+        );
     }
 
-    chart.configure_series_labels().border_style(BLACK).draw()?;
+    chart.group(Unit::Fixed("count".to_owned()));
+    chart.limit(
+        "RLIMIT_NOFILE",
+        limit_series(&limits, |l: &Limits| l.nofile).map(|(x, limit)| (x, limit as f64)),
+    );
 
-    root.present()?;
+    chart.draw(theme, axes, markers, format, output.as_ref())
+}
 
-    drop(chart);
-    drop(root);
+pub fn render_sockets<P>(
+    data: &[Data],
+    markers: &[(usize, Marker)],
+    theme: &Theme,
+    axes: Axes,
+    format: Format,
+    output: P,
+) -> anyhow::Result<()>
+where
+    P: AsRef<Path>,
+{
+    // 出现过的全部(协议, 状态)
+    let states = data
+        .iter()
+        .filter_map(|data| data.fd.as_ref())
+        .flat_map(|fd| {
+            fd.tcp
+                .keys()
+                .map(|state| ("tcp", state.clone()))
+                .chain(fd.udp.keys().map(|state| ("udp", state.clone())))
+        })
+        .collect::<BTreeSet<_>>();
+    let count = |data: &Data, protocol: &str, state: &str| {
+        data.fd
+            .as_ref()
+            .and_then(|fd| match protocol {
+                "tcp" => fd.tcp.get(state),
+                _ => fd.udp.get(state),
+            })
+            .copied()
+            .unwrap_or(0)
+    };
 
-    Ok(())
+    let mut chart = Chart::new("Sockets", data, Unit::Fixed("count".to_owned()));
+    for (protocol, state) in &states {
+        chart.data(
+            format!("{} {}", protocol, state),
+            data.iter()
+                .enumerate()
+                .map(|(x, data)| (x, count(data, protocol, state) as f64)),
+        );
+    }
+
+    chart.draw(theme, axes, markers, format, output.as_ref())
 }
 
-pub fn render_io<P>(data: &[Data], output: P) -> anyhow::Result<()>
+// 图表中最多绘制的文件和设备数
+pub const TOP_IO: usize = 10;
+
+// 读写进度最大的文件, 见`file_progress`
+pub fn render_files<P>(
+    data: &[Data],
+    markers: &[(usize, Marker)],
+    theme: &Theme,
+    axes: Axes,
+    format: Format,
+    output: P,
+) -> anyhow::Result<()>
 where
     P: AsRef<Path>,
 {
-    let to_mb = |b: u64| Byte::from(b).get_adjusted_unit(ByteUnit::MB).get_value() as u64;
+    let files = file_progress(data);
 
-    let x_len = data.len();
-    let y_len = [
-        data.iter()
-            .map(|data| data.io.bytes_written)
-            .map(to_mb)
-            .max()
-            .unwrap_or(0),
-        data.iter()
-            .map(|data| data.io.bytes_read)
-            .map(to_mb)
-            .max()
-            .unwrap_or(0),
-        data.iter()
-            .filter_map(|data| data.io.disk_written)
-            .map(to_mb)
-            .max()
-            .unwrap_or(0),
-        data.iter()
-            .filter_map(|data| data.io.disk_read)
-            .map(to_mb)
-            .max()
-            .unwrap_or(0),
-        data.iter()
-            .filter_map(|data| data.io.syscall_written)
-            .map(to_mb)
-            .max()
-            .unwrap_or(0),
+    let mut chart = Chart::new("Top Files", data, Unit::Bytes);
+    for (path, mode, series) in files.iter().take(TOP_IO) {
+        chart.data(
+            format!("{} ({})", path, mode),
+            series
+                .iter()
+                .enumerate()
+                .map(|(x, progress)| (x, *progress as f64)),
+        );
+    }
+
+    chart.draw(theme, axes, markers, format, output.as_ref())
+}
+
+// 读写最多的块设备, 见`device_io`
+pub fn render_devices<P>(
+    data: &[Data],
+    markers: &[(usize, Marker)],
+    theme: &Theme,
+    axes: Axes,
+    format: Format,
+    output: P,
+) -> anyhow::Result<()>
+where
+    P: AsRef<Path>,
+{
+    let devices = device_io(data);
+
+    let mut chart = Chart::new("Top Devices", data, Unit::Bytes);
+    for (name, series) in devices.iter().take(TOP_IO) {
+        // 读用同一颜色的半透明的线
+        let color = chart.next_color();
+        chart.series(
+            format!("{} written", name),
+            color,
+            Line::Data,
+            series
+                .iter()
+                .enumerate()
+                .map(|(x, io)| (x, io.written as f64)),
+        );
+        chart.series(
+            format!("{} read", name),
+            color,
+            Line::Host,
+            series.iter().enumerate().map(|(x, io)| (x, io.read as f64)),
+        );
+    }
+
+    chart.draw(theme, axes, markers, format, output.as_ref())
+}
+
+// 一个自定义指标, 没有收到过的采样跳过
+pub fn render_custom<P>(
+    data: &[Data],
+    markers: &[(usize, Marker)],
+    name: &str,
+    theme: &Theme,
+    axes: Axes,
+    format: Format,
+    output: P,
+) -> anyhow::Result<()>
+where
+    P: AsRef<Path>,
+{
+    let mut chart = Chart::new(name, data, Unit::Fixed(String::new()));
+    chart.data(
+        name,
         data.iter()
-            .filter_map(|data| data.io.syscall_read)
-            .map(to_mb)
-            .max()
-            .unwrap_or(0),
-    ]
-    .iter()
-    .max()
-    .cloned()
-    .unwrap_or(0);
-    let root = SVGBackend::new(&output, (1920, 1080)).into_drawing_area();
-
-    root.fill(&WHITE)?;
-
-    let mut chart = ChartBuilder::on(&root)
-        .set_label_area_size(LabelAreaPosition::Left, (8).percent())
-        .set_label_area_size(LabelAreaPosition::Bottom, (4).percent())
-        .caption("I/O", ("sans-serif", (5).percent_height()))
-        .margin((1).percent())
-        .build_cartesian_2d(0..x_len, 0..y_len)?;
-
-    chart.configure_mesh().y_desc("MB").draw()?;
-
-    let elems: Vec<(&'static str, RGBColor, Box<dyn Fn(&Data) -> u64>)> = vec![
-        ("bytes_written", RED, box |d: &Data| d.io.bytes_written),
-        ("bytes_read", GREEN, box |d: &Data| d.io.bytes_read),
-        ("disk_written", BLUE, box |d: &Data| {
-            d.io.disk_written.unwrap_or_default()
-        }),
-        ("disk_read", YELLOW, box |d: &Data| {
-            d.io.disk_read.unwrap_or_default()
-        }),
-        ("syscall_written", MAGENTA, box |d: &Data| {
-            d.io.syscall_written.unwrap_or_default()
-        }),
-        ("syscall_read", CYAN, box |d: &Data| {
-            d.io.syscall_read.unwrap_or_default()
-        }),
+            .enumerate()
+            .filter_map(|(x, data)| Some((x, *data.custom.as_ref()?.get(name)?))),
+    );
+
+    chart.draw(theme, axes, markers, format, output.as_ref())
+}
+
+// 每个采样的压力, 以及出现过的全部名称
+fn psi_lines(data: &[Data]) -> (BTreeSet<String>, Vec<HashMap<String, &PressureLine>>) {
+    let lines = data
+        .iter()
+        .map(|data| {
+            data.psi
+                .as_ref()
+                .map(|psi| psi.lines().collect())
+                .unwrap_or_default()
+        })
+        .collect::<Vec<HashMap<_, _>>>();
+    let names = lines
+        .iter()
+        .flat_map(|lines| lines.keys().cloned())
+        .collect();
+    (names, lines)
+}
+
+pub fn render_pressure<P>(
+    data: &[Data],
+    markers: &[(usize, Marker)],
+    theme: &Theme,
+    axes: Axes,
+    format: Format,
+    output: P,
+) -> anyhow::Result<()>
+where
+    P: AsRef<Path>,
+{
+    let (names, lines) = psi_lines(data);
+
+    let mut chart = Chart::new("Pressure (avg10)", data, Unit::Fixed("%".to_owned()));
+    for name in &names {
+        chart.data(
+            name,
+            lines.iter().enumerate().map(|(x, lines)| {
+                (
+                    x,
+                    lines.get(name).map(|line| line.avg10 as f64).unwrap_or(0.0),
+                )
+            }),
+        );
+    }
+
+    chart.draw(theme, axes, markers, format, output.as_ref())
+}
+
+pub fn render_stall_time<P>(
+    data: &[Data],
+    markers: &[(usize, Marker)],
+    theme: &Theme,
+    axes: Axes,
+    format: Format,
+    output: P,
+) -> anyhow::Result<()>
+where
+    P: AsRef<Path>,
+{
+    let (names, lines) = psi_lines(data);
+
+    // 从记录开始累计的停顿时间, 微秒
+    let first = |name: &String| {
+        lines
+            .iter()
+            .find_map(|lines| lines.get(name))
+            .map(|line| line.total)
+            .unwrap_or(0)
+    };
+    let stalled = |lines: &HashMap<String, &PressureLine>, name: &String| {
+        lines
+            .get(name)
+            .map(|line| line.total.saturating_sub(first(name)) as f64)
+            .unwrap_or(0.0)
+    };
+
+    let mut chart = Chart::new("Stall Time", data, Unit::Micros);
+    for name in &names {
+        chart.data(
+            name,
+            lines
+                .iter()
+                .enumerate()
+                .map(|(x, lines)| (x, stalled(lines, name))),
+        );
+    }
+
+    chart.draw(theme, axes, markers, format, output.as_ref())
+}
+
+// `render --plot`的指标的单位, 按路径推断
+fn metric_unit(metric: &Metric) -> Unit {
+    let path = metric.path.as_str();
+    const BYTES: &[&str] = &[
+        "memory.",
+        "io.storage_",
+        "io.chars_",
+        "io.cancelled_",
+        "cgroup.memory_",
+        "host.memory_",
+        "host.swap_",
+        "host.disk_",
+        "host.net_",
+        "io_attribution.",
     ];
+    const MICROS: &[&str] = &["cpu_time.", "cgroup.cpu_stat."];
+    const PERCENT: &[&str] = &["cpu_usage", "host.cpu_usage", "cores.usage."];
 
-    for (label, color, elem) in elems {
-        chart
-            .draw_series(LineSeries::new(
-                data.iter()
-                    .enumerate()
-                    .map(|(x, data)| (x, to_mb(elem(data)))),
-                color.stroke_width(3),
-            ))?
-            .label(label)
-            .legend(move |(x, y)| Rectangle::new([(x, y - 5), (x + 10, y + 5)], color.filled()));
+    let prefixed = |prefixes: &[&str]| prefixes.iter().any(|prefix| path.starts_with(prefix));
+    let unit = if prefixed(BYTES) || path.contains("bytes") {
+        Unit::Bytes
+    } else if prefixed(MICROS) {
+        Unit::Micros
+    } else if prefixed(PERCENT) {
+        Unit::Fixed("%".to_owned())
+    } else {
+        Unit::Fixed(String::new())
+    };
+    match metric.transform {
+        Transform::Rate => Unit::PerSecond(Box::new(unit)),
+        Transform::None | Transform::Delta => unit,
     }
+}
 
-    chart.configure_series_labels().border_style(BLACK).draw()?;
+// 任意指标绘制在一个图表中, 单位不同的指标为不同的组.
+// `restarts`是跟随重启时新进程的第一个采样, 见`plot::restarts`
+#[allow(clippy::too_many_arguments)]
+pub fn render_plot<P>(
+    data: &[Data],
+    markers: &[(usize, Marker)],
+    restarts: &[usize],
+    plot: &Plot,
+    theme: &Theme,
+    axes: Axes,
+    format: Format,
+    output: P,
+) -> anyhow::Result<()>
+where
+    P: AsRef<Path>,
+{
+    let json = data
+        .iter()
+        .map(serde_json::to_value)
+        .collect::<Result<Vec<_>, _>>()?;
 
-    root.present()?;
+    // 按单位分组, 组的顺序为单位第一次出现的顺序
+    let mut groups: Vec<(Unit, Vec<&Metric>)> = Vec::new();
+    for metric in &plot.0 {
+        let unit = metric_unit(metric);
+        match groups.iter_mut().find(|(u, _)| *u == unit) {
+            Some((_, metrics)) => metrics.push(metric),
+            None => groups.push((unit, vec![metric])),
+        }
+    }
 
-    drop(chart);
-    drop(root);
+    let mut chart = Chart::new("Plot", data, groups[0].0.clone());
+    for (i, (unit, metrics)) in groups.into_iter().enumerate() {
+        if i > 0 {
+            chart.group(unit);
+        }
+        for metric in metrics {
+            let series = metric.series(data, &json, restarts);
+            anyhow::ensure!(
+                !series.is_empty(),
+                "no values for metric {} in the recording",
+                metric.expr
+            );
+            chart.data(&metric.expr, series);
+        }
+    }
 
-    Ok(())
+    chart.draw(theme, axes, markers, format, output.as_ref())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::tests::sample;
+
+    // 每条曲线的值依次为第0, 1, 2...个采样
+    fn group(unit: Unit, series: &[&[f64]]) -> Group {
+        Group {
+            unit,
+            series: series
+                .iter()
+                .map(|values| Series {
+                    label: String::new(),
+                    color: 0,
+                    line: Line::Data,
+                    points: values.iter().copied().enumerate().collect(),
+                })
+                .collect(),
+        }
+    }
+
+    fn count() -> Unit {
+        Unit::Fixed("count".to_owned())
+    }
+
+    #[test]
+    fn x_axis_uses_time() {
+        let data = [
+            sample(Some(10_000)),
+            sample(Some(10_100)),
+            sample(Some(10_200)),
+            sample(Some(15_200)),
+        ];
+        let x = XAxis::new(&data);
+        assert_eq!(x.xs, [0.0, 0.1, 0.2, 5.2]);
+        assert_eq!(x.desc, "s");
+        assert_eq!(x.range(), 0.0..5.2);
+        // 最后一个采样之后的标记
+        assert_eq!(x.at(4), 5.2);
+    }
+
+    #[test]
+    fn x_axis_falls_back_to_index() {
+        let data = [sample(None), sample(Some(10_000)), sample(None)];
+        let x = XAxis::new(&data);
+        assert_eq!(x.xs, [0.0, 1.0, 2.0]);
+        assert_eq!(x.desc, "");
+        assert_eq!(x.range(), 0.0..3.0);
+
+        assert_eq!(XAxis::new(&[]).range(), 0.0..1.0);
+    }
+
+    #[test]
+    fn unit_scale() {
+        const KIB: f64 = 1024.0;
+        let per_second = |unit| Unit::PerSecond(Box::new(unit));
+        let cases = [
+            (Unit::Bytes, 0.0, Units::Binary, 1.0, "B"),
+            (Unit::Bytes, 1000.0, Units::Binary, 1.0, "B"),
+            (Unit::Bytes, 1536.0, Units::Binary, KIB, "KiB"),
+            (Unit::Bytes, 1536.0, Units::Decimal, 1e3, "KB"),
+            (
+                Unit::Bytes,
+                300.0 * KIB * KIB,
+                Units::Binary,
+                KIB * KIB,
+                "MiB",
+            ),
+            (
+                Unit::Bytes,
+                5.0 * KIB * KIB * KIB,
+                Units::Binary,
+                KIB * KIB * KIB,
+                "GiB",
+            ),
+            (Unit::Bytes, 5e9, Units::Decimal, 1e9, "GB"),
+            (Unit::Micros, 0.0, Units::Binary, 1.0, "µs"),
+            (Unit::Micros, 999.0, Units::Binary, 1.0, "µs"),
+            (Unit::Micros, 1000.0, Units::Binary, 1e3, "ms"),
+            (Unit::Micros, 2_500_000.0, Units::Binary, 1e6, "s"),
+            (count(), 1e9, Units::Decimal, 1.0, "count"),
+            (per_second(Unit::Micros), 5000.0, Units::Binary, 1e3, "ms/s"),
+            (per_second(Unit::Bytes), 2e6, Units::Decimal, 1e6, "MB/s"),
+        ];
+        for (unit, max, units, factor, desc) in cases {
+            let scale = unit.scale(max, units);
+            assert_eq!(
+                (scale.factor, scale.unit.as_str()),
+                (factor, desc),
+                "{:?} {} {:?}",
+                unit,
+                max,
+                units
+            );
+        }
+    }
+
+    #[test]
+    fn axis_range() {
+        let cases: &[(&[&[f64]], Range<f64>)] = &[
+            // 全部为0时也有刻度
+            (&[&[0.0, 0.0]], 0.0..1.0),
+            (&[&[1.0, 5.0], &[3.0]], 0.0..5.0),
+            (&[&[-2.0, 3.0], &[1.0]], -2.0..3.0),
+            // 全部为负数时包含0
+            (&[&[-5.0, -1.0]], -5.0..0.0),
+        ];
+        for (series, range) in cases {
+            let groups = [group(count(), series)];
+            let axis = Axis::new(&groups, &[0], Units::Binary, false);
+            assert_eq!(axis.range, *range, "{:?}", series);
+            assert_eq!(axis.desc(), "count");
+        }
+
+        // 范围和值都换算为最大值的单位
+        let groups = [group(Unit::Bytes, &[&[512.0, 3.0 * 1024.0 * 1024.0]])];
+        let axis = Axis::new(&groups, &[0], Units::Binary, false);
+        assert_eq!(axis.desc(), "MiB");
+        assert_eq!(axis.range, 0.0..3.0);
+        assert_eq!(axis.value(512.0 * 1024.0), 0.5);
+
+        // 一个y轴上的多组曲线
+        let groups = [group(count(), &[&[1.0]]), group(count(), &[&[-1.0, 8.0]])];
+        let axis = Axis::new(&groups, &[0, 1], Units::Binary, false);
+        assert_eq!(axis.series.len(), 2);
+        assert_eq!(axis.range, -1.0..8.0);
+    }
+
+    #[test]
+    fn log_axis_range() {
+        let cases: &[(&[&[f64]], Range<f64>)] = &[
+            // 扩展到整数的数量级
+            (&[&[3.0, 250.0]], 1.0..1000.0),
+            (&[&[0.02, 0.5]], 0.01..1.0),
+            // 0和负数不影响范围
+            (&[&[0.0, -4.0, 20.0, 30.0]], 10.0..100.0),
+            // 不足一个数量级时也有刻度
+            (&[&[10.0]], 10.0..100.0),
+            // 没有正数
+            (&[&[0.0, 0.0]], 1.0..10.0),
+            (&[&[-1.0, -5.0]], 1.0..10.0),
+            (&[], 1.0..10.0),
+        ];
+        for (series, range) in cases {
+            let groups = [group(count(), series)];
+            let axis = Axis::new(&groups, &[0], Units::Binary, true);
+            assert_eq!(axis.range, *range, "{:?}", series);
+        }
+
+        // 0和负数绘制在底部
+        let groups = [group(count(), &[&[0.0, -4.0, 20.0]])];
+        let axis = Axis::new(&groups, &[0], Units::Binary, true);
+        assert_eq!(axis.value(0.0), 10.0);
+        assert_eq!(axis.value(-4.0), 10.0);
+        assert_eq!(axis.value(20.0), 20.0);
+        // 线性坐标保留负数
+        let axis = Axis::new(&groups, &[0], Units::Binary, false);
+        assert_eq!(axis.value(-4.0), -4.0);
+
+        // 对数坐标的范围也换算单位
+        let groups = [group(Unit::Bytes, &[&[0.0, 3.0 * 1024.0 * 1024.0]])];
+        let axis = Axis::new(&groups, &[0], Units::Binary, true);
+        assert_eq!(axis.desc(), "MiB");
+        assert_eq!(axis.range, 1.0..10.0);
+    }
+
+    #[test]
+    fn layout() {
+        // 添加的组: (单位, 是否有曲线)
+        let chart = |groups: &[(Unit, bool)]| {
+            let mut chart = Chart::new("test", &[], groups[0].0.clone());
+            for (i, (unit, used)) in groups.iter().enumerate() {
+                if i > 0 {
+                    chart.group(unit.clone());
+                }
+                if *used {
+                    chart.data("a", [(0, 1.0)]);
+                }
+            }
+            chart
+        };
+        let axes = |secondary, split| Axes {
+            log: false,
+            secondary,
+            split,
+        };
+        let same = [
+            (Unit::Bytes, true),
+            (Unit::Bytes, true),
+            (Unit::Bytes, true),
+        ];
+        let mixed = [(count(), true), (Unit::Bytes, true), (Unit::Bytes, true)];
+        let three = [(count(), true), (Unit::Bytes, true), (Unit::Micros, true)];
+        let single = [(Unit::Bytes, true), (count(), false)];
+        let unused = [(Unit::Bytes, false), (count(), true), (count(), true)];
+        let empty = [(Unit::Bytes, false)];
+        type Panels = Vec<(Vec<usize>, Vec<usize>)>;
+        let cases: Vec<(&[(Unit, bool)], Axes, Panels)> = vec![
+            // 单位相同的组绘制在同一个y轴上
+            (&same, axes(false, false), vec![(vec![0, 1, 2], vec![])]),
+            // 单位不同的组绘制在不同的子图中
+            (
+                &mixed,
+                axes(false, false),
+                vec![(vec![0], vec![]), (vec![1], vec![]), (vec![2], vec![])],
+            ),
+            (
+                &same,
+                axes(false, true),
+                vec![(vec![0], vec![]), (vec![1], vec![]), (vec![2], vec![])],
+            ),
+            // 第一组在左侧, 其他组在右侧
+            (&same, axes(true, false), vec![(vec![0], vec![1, 2])]),
+            (&mixed, axes(true, false), vec![(vec![0], vec![1, 2])]),
+            // 右侧的组单位不同时只能分开绘制
+            (
+                &three,
+                axes(true, false),
+                vec![(vec![0], vec![]), (vec![1], vec![]), (vec![2], vec![])],
+            ),
+            // split优先于secondary
+            (
+                &mixed,
+                axes(true, true),
+                vec![(vec![0], vec![]), (vec![1], vec![]), (vec![2], vec![])],
+            ),
+            // 只有一组时没有右侧的y轴
+            (&single, axes(true, false), vec![(vec![0], vec![])]),
+            // 没有曲线的组跳过
+            (&unused, axes(false, false), vec![(vec![1, 2], vec![])]),
+            // 没有数据时绘制空的坐标轴
+            (&empty, axes(true, true), vec![(vec![0], vec![])]),
+        ];
+        for (groups, axes, panels) in cases {
+            assert_eq!(chart(groups).layout(axes), panels, "{:?}", axes);
+        }
+    }
 }
@@ -0,0 +1,123 @@
+// 与进程同时采样的系统整体数据, 作为对比的基准
+use futures::stream::StreamExt;
+use heim::units::{information, time};
+
+use crate::data::Host;
+
+// 一个核的cpu时间, 秒
+struct CoreTime {
+    busy: f64,
+    total: f64,
+}
+
+impl From<heim::cpu::CpuTime> for CoreTime {
+    fn from(cpu_time: heim::cpu::CpuTime) -> Self {
+        let busy = cpu_time.user().get::<time::second>() + cpu_time.system().get::<time::second>();
+        let idle = cpu_time.idle().get::<time::second>();
+
+        cfg_if::cfg_if! {
+            if #[cfg(target_os = "linux")] {
+                use heim::cpu::os::linux::CpuTimeExt;
+
+                let busy = busy
+                    + cpu_time.nice().get::<time::second>()
+                    + cpu_time.irq().get::<time::second>()
+                    + cpu_time.soft_irq().get::<time::second>()
+                    + cpu_time.steal().get::<time::second>();
+                let idle = idle + cpu_time.io_wait().get::<time::second>();
+            }
+        }
+
+        CoreTime {
+            busy,
+            total: busy + idle,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct HostCollector {
+    last_core_times: Vec<CoreTime>,
+}
+
+impl HostCollector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub async fn collect(&mut self) -> anyhow::Result<Host> {
+        let mut core_times = Vec::new();
+        let times = heim::cpu::times().await?;
+        pin_utils::pin_mut!(times);
+        while let Some(cpu_time) = times.next().await {
+            core_times.push(CoreTime::from(cpu_time?));
+        }
+
+        // 第一次采样没有上一次的cpu时间, 视为0
+        let cpu_usage_per_core = core_times
+            .iter()
+            .enumerate()
+            .map(|(i, now)| {
+                self.last_core_times
+                    .get(i)
+                    .filter(|last| now.total > last.total)
+                    .map(|last| ((now.busy - last.busy) / (now.total - last.total) * 100.0) as f32)
+                    .unwrap_or(0f32)
+            })
+            .collect::<Vec<_>>();
+        self.last_core_times = core_times;
+
+        cfg_if::cfg_if! {
+            if #[cfg(unix)] {
+                use heim::units::ratio;
+
+                let (one, five, fifteen) = heim::cpu::os::unix::loadavg().await?;
+                let load_average = Some([
+                    one.get::<ratio::ratio>() as f64,
+                    five.get::<ratio::ratio>() as f64,
+                    fifteen.get::<ratio::ratio>() as f64,
+                ]);
+            } else {
+                let load_average = None;
+            }
+        }
+
+        let memory = heim::memory::memory().await?;
+        let swap = heim::memory::swap().await?;
+
+        let (mut disk_read, mut disk_written) = (0, 0);
+        let disks = heim::disk::io_counters_physical().await?;
+        pin_utils::pin_mut!(disks);
+        while let Some(disk) = disks.next().await {
+            let disk = disk?;
+            disk_read += disk.read_bytes().get::<information::byte>();
+            disk_written += disk.write_bytes().get::<information::byte>();
+        }
+
+        let (mut net_recv, mut net_sent) = (0, 0);
+        let nics = heim::net::io_counters().await?;
+        pin_utils::pin_mut!(nics);
+        while let Some(nic) = nics.next().await {
+            let nic = nic?;
+            if nic.interface() == "lo" {
+                continue;
+            }
+            net_recv += nic.bytes_recv().get::<information::byte>();
+            net_sent += nic.bytes_sent().get::<information::byte>();
+        }
+
+        Ok(Host {
+            cpu_usage: cpu_usage_per_core.iter().sum(),
+            cpu_usage_per_core,
+            load_average,
+            memory_total: memory.total().get::<information::byte>(),
+            memory_available: memory.available().get::<information::byte>(),
+            swap_total: swap.total().get::<information::byte>(),
+            swap_used: swap.used().get::<information::byte>(),
+            disk_read,
+            disk_written,
+            net_recv,
+            net_sent,
+        })
+    }
+}
@@ -1,8 +1,19 @@
-#![feature(box_syntax)]
 #![allow(clippy::type_complexity)]
 
+pub mod backoff;
+#[cfg(target_os = "linux")]
+pub mod cgroup;
 pub mod data;
 pub mod draw;
+pub mod host;
+#[cfg(unix)]
+pub mod marker;
+pub mod metrics;
 pub mod monitor;
-pub mod shutdown_notify;
+pub mod plot;
+#[cfg(target_os = "linux")]
+pub mod procfs;
 pub mod store;
+pub mod supervisor;
+pub mod theme;
+pub mod trigger;
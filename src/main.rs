@@ -1,14 +1,27 @@
+use std::collections::BTreeMap;
 use std::fs::OpenOptions;
+use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::time::Duration;
 
 use argh::FromArgs;
+use byte_unit::Byte;
 use heim::process::Pid;
 
-use clairvoyance::draw::{render_cpu_time, render_cpu_usage, render_io, render_memory};
-use clairvoyance::monitor::Monitor;
-use clairvoyance::shutdown_notify::ShutdownNotify;
+use clairvoyance::data::{
+    custom_names, device_io, file_progress, stall_summary, EventKind, Marker, Record,
+};
+use clairvoyance::draw::{
+    render_cpu_cores, render_cpu_time, render_cpu_usage, render_custom, render_devices, render_fd,
+    render_files, render_io, render_io_syscalls, render_memory, render_plot, render_pressure,
+    render_sched, render_sockets, render_stall_time, Axes, Format, CHARTS, TOP_IO,
+};
+use clairvoyance::monitor::{Adaptive, Collectors, Monitor, Selector, Stop};
+use clairvoyance::plot::{restarts, Plot};
 use clairvoyance::store::StoreStream;
+use clairvoyance::supervisor::{ExitReason, StopPolicy, Supervisor};
+use clairvoyance::theme::{Overrides, Palette, Theme, Units};
+use clairvoyance::trigger::Trigger;
 
 #[tokio::main]
 async fn main() {
@@ -18,32 +31,290 @@ async fn main() {
 
     match args.sub_cmd {
         SubCommandEnum::Record(args) => {
-            if args.pid.is_empty() {
+            if args.pid.is_empty()
+                && args.name.is_empty()
+                && args.cmdline.is_empty()
+                && args.cgroup.is_empty()
+                && args.unit.is_empty()
+            {
                 log::warn!("no process that needs to record");
                 return;
             }
 
-            let shutdown_handle = ShutdownNotify::new();
+            if args.follow_restarts && !args.pid.is_empty() {
+                log::warn!(
+                    "--follow-restarts only applies to processes selected by --name or --cmdline"
+                );
+            }
+
+            let supervisor = Supervisor::new();
+            let collectors = Collectors {
+                fd: args.fd,
+                psi: args.psi,
+                host: args.host,
+                cores: args.cores,
+                files: args.files,
+                env: args.env,
+            };
 
+            let mut monitors = Vec::new();
             for pid in args.pid {
-                let mut monitor = Monitor::from_pid(pid, &args.out_dir, shutdown_handle.start())
+                monitors.push(
+                    Monitor::from_pid(
+                        pid,
+                        &args.out_dir,
+                        collectors.clone(),
+                        supervisor.start().unwrap(),
+                    )
+                    .await
+                    .unwrap(),
+                );
+            }
+            // 只有按名字或命令行选择的进程才能跟随重启
+            let selectors = args
+                .name
+                .into_iter()
+                .map(Selector::Name)
+                .chain(args.cmdline.into_iter().map(Selector::Cmdline));
+            for selector in selectors {
+                let mut monitor = Monitor::from_selector(
+                    selector,
+                    &args.out_dir,
+                    collectors.clone(),
+                    supervisor.start().unwrap(),
+                )
+                .await
+                .unwrap();
+                monitor.set_follow_restarts(args.follow_restarts);
+                monitors.push(monitor);
+            }
+
+            cfg_if::cfg_if! {
+                if #[cfg(target_os = "linux")] {
+                    for cgroup in args.cgroup {
+                        monitors.push(
+                            Monitor::from_cgroup(&cgroup, &args.out_dir, collectors.clone(), supervisor.start().unwrap())
+                                .await
+                                .unwrap(),
+                        );
+                    }
+                    for unit in args.unit {
+                        monitors.push(
+                            Monitor::from_unit(&unit, &args.out_dir, collectors.clone(), supervisor.start().unwrap())
+                                .await
+                                .unwrap(),
+                        );
+                    }
+                } else {
+                    if !args.cgroup.is_empty() || !args.unit.is_empty() {
+                        log::error!("recording cgroups is only supported on linux");
+                        return;
+                    }
+                }
+            }
+
+            let stop = Stop {
+                samples: args.samples,
+                idle_for: args.until_idle.map(|idle| idle.0),
+                idle_cpu: args.idle_cpu,
+            };
+            for monitor in &mut monitors {
+                monitor.set_stop(stop);
+            }
+
+            if let Some(fast) = args.burst {
+                let adaptive = Adaptive {
+                    fast: fast.0,
+                    cpu_threshold: args.burst_cpu,
+                    rss_threshold: args.burst_rss,
+                };
+                for monitor in &mut monitors {
+                    monitor.set_adaptive(adaptive);
+                }
+            }
+
+            if args.trigger_rss.is_some() || args.trigger_cpu.is_some() || args.trigger_exit {
+                let trigger = Trigger {
+                    rss: args.trigger_rss.map(|rss| rss.0),
+                    cpu: args.trigger_cpu,
+                    cpu_for: args.trigger_cpu_for.0,
+                    exit: args.trigger_exit,
+                    before: args.before.0,
+                    after: args.after.0,
+                };
+                for monitor in &mut monitors {
+                    monitor.set_trigger(trigger);
+                }
+            }
+
+            if let Some(addr) = args.metrics {
+                let (sender, _) = tokio::sync::broadcast::channel(1024);
+                clairvoyance::metrics::listen(addr, sender.clone())
                     .await
                     .unwrap();
+                for monitor in &mut monitors {
+                    monitor.subscribe_metrics(sender.subscribe());
+                }
+            }
+
+            cfg_if::cfg_if! {
+                if #[cfg(unix)] {
+                    // SIGHUP轮换中间文件
+                    let (sender, _) = tokio::sync::broadcast::channel(1);
+                    for monitor in &mut monitors {
+                        monitor.subscribe_rotate(sender.subscribe());
+                    }
+                    tokio::spawn(async move {
+                        use tokio::signal::unix::{signal, SignalKind};
+
+                        let mut hangup = signal(SignalKind::hangup()).unwrap();
+                        while hangup.recv().await.is_some() {
+                            let _ = sender.send(());
+                        }
+                    });
+
+                    if let Some(path) = &args.mark_socket {
+                        let (sender, _) = tokio::sync::broadcast::channel(16);
+                        clairvoyance::marker::listen(path, sender.clone()).unwrap();
+                        for monitor in &mut monitors {
+                            monitor.subscribe_markers(sender.subscribe());
+                        }
+                    }
+                } else {
+                    if args.mark_socket.is_some() {
+                        log::error!("markers are only supported on unix");
+                        return;
+                    }
+                }
+            }
+
+            for mut monitor in monitors {
                 tokio::spawn(async move {
                     monitor.run(args.frequency.0).await;
                 });
             }
 
-            shutdown_handle.wait_shutdown(args.shutdown_timeout.0).await;
+            if let Some(duration) = args.duration {
+                let supervisor = supervisor.clone();
+                tokio::spawn(async move {
+                    tokio::time::sleep(duration.0).await;
+                    log::info!("recorded for {:?}", duration.0);
+                    supervisor.shutdown();
+                });
+            }
+
+            let results = supervisor
+                .wait_shutdown(args.shutdown_timeout.0, args.stop_on)
+                .await;
+
+            if let Some(path) = &args.mark_socket {
+                std::fs::remove_file(path).ok();
+            }
+
+            // 中间文件写入失败时记录不完整
+            if results
+                .iter()
+                .any(|result| matches!(result.reason, ExitReason::Store(_)))
+            {
+                std::process::exit(1);
+            }
+        }
+        SubCommandEnum::Mark(args) => {
+            cfg_if::cfg_if! {
+                if #[cfg(unix)] {
+                    if let Err(err) = clairvoyance::marker::send(&args.socket, &args.label).await {
+                        log::error!("{}", err);
+                    }
+                } else {
+                    let _ = args;
+                    log::error!("markers are only supported on unix");
+                }
+            }
         }
         SubCommandEnum::Render(args) => {
+            if !args.format.supported() {
+                log::error!(
+                    "this build does not support {} output, rebuild with the {} feature",
+                    args.format.extension(),
+                    args.format.extension()
+                );
+                std::process::exit(1);
+            }
+
+            // 命令行的选项覆盖主题中的值
+            let theme = match &args.theme {
+                Some(name) => match Theme::load(name) {
+                    Ok(theme) => theme,
+                    Err(err) => {
+                        log::error!("{}", err);
+                        std::process::exit(1);
+                    }
+                },
+                None => Theme::default(),
+            }
+            .with(Overrides {
+                size: args.size.map(|ParseSize(size)| size),
+                palette: args.palette,
+                line_width: args.line_width,
+                font: args.font,
+                units: args.decimal.then_some(Units::Decimal),
+            });
+
+            for name in args
+                .log
+                .iter()
+                .chain(&args.secondary_axis)
+                .chain(&args.split)
+            {
+                if !CHARTS.contains(&name.as_str()) {
+                    log::error!(
+                        "unknown chart {}, expected one of {}",
+                        name,
+                        CHARTS.join(", ")
+                    );
+                    std::process::exit(1);
+                }
+            }
+            let axes = |name: &str| Axes {
+                log: args.log.iter().any(|chart| chart == name),
+                secondary: args.secondary_axis.iter().any(|chart| chart == name),
+                split: args.split.iter().any(|chart| chart == name),
+            };
+
             let mut stream = StoreStream::open(args.file)
                 .await
                 .expect("failed to open store stream");
             let mut data = Vec::new();
-            while let Some(d) = stream.read().await.unwrap() {
-                data.push(d);
+            // 标记和之后的第一个采样的位置
+            let mut markers = Vec::new();
+            let mut segments = Vec::new();
+            let mut errors = Vec::new();
+            let mut events = Vec::new();
+            while let Some(record) = stream.read().await.unwrap() {
+                match record {
+                    Record::Data(d) => data.push(d),
+                    Record::Marker(m) => markers.push((data.len(), m)),
+                    Record::Segment(s) => segments.push((data.len(), s)),
+                    Record::Error(e) => errors.push(e),
+                    Record::Event(e) => events.push((data.len(), e)),
+                }
+            }
+            if let Some(process) = stream.header().and_then(|header| header.process.as_ref()) {
+                log::info!("recorded process:\n{}", process);
+            }
+            // 采集失败的项在图表中没有值
+            let mut failed = BTreeMap::new();
+            for error in &errors {
+                *failed.entry(error.collector.as_str()).or_insert(0) += 1;
+            }
+            for (collector, count) in failed {
+                log::warn!("{} failed {} times during recording", collector, count);
             }
+            // 旧版本的文件没有记录核数, 只能使用当前机器的
+            let cores = stream
+                .header()
+                .map(|header| header.cores)
+                .unwrap_or_else(|| num_cpus::get() as u32);
 
             if args.json {
                 serde_json::to_writer(
@@ -56,17 +327,298 @@ async fn main() {
                     &data,
                 )
                 .unwrap();
+                if !segments.is_empty() {
+                    serde_json::to_writer(
+                        OpenOptions::new()
+                            .write(true)
+                            .truncate(true)
+                            .create(true)
+                            .open(args.out_dir.join("segments.json"))
+                            .unwrap(),
+                        &segments.iter().map(|(_, s)| s).collect::<Vec<_>>(),
+                    )
+                    .unwrap();
+                }
+                if let Some(header) = stream.header() {
+                    serde_json::to_writer(
+                        OpenOptions::new()
+                            .write(true)
+                            .truncate(true)
+                            .create(true)
+                            .open(args.out_dir.join("header.json"))
+                            .unwrap(),
+                        header,
+                    )
+                    .unwrap();
+                }
+                if !events.is_empty() {
+                    serde_json::to_writer(
+                        OpenOptions::new()
+                            .write(true)
+                            .truncate(true)
+                            .create(true)
+                            .open(args.out_dir.join("events.json"))
+                            .unwrap(),
+                        &events.iter().map(|(_, e)| e).collect::<Vec<_>>(),
+                    )
+                    .unwrap();
+                }
+                if !errors.is_empty() {
+                    serde_json::to_writer(
+                        OpenOptions::new()
+                            .write(true)
+                            .truncate(true)
+                            .create(true)
+                            .open(args.out_dir.join("errors.json"))
+                            .unwrap(),
+                        &errors,
+                    )
+                    .unwrap();
+                }
+                if !markers.is_empty() {
+                    serde_json::to_writer(
+                        OpenOptions::new()
+                            .write(true)
+                            .truncate(true)
+                            .create(true)
+                            .open(args.out_dir.join("markers.json"))
+                            .unwrap(),
+                        &markers.iter().map(|(_, m)| m).collect::<Vec<_>>(),
+                    )
+                    .unwrap();
+                }
             }
 
+            let restarts = restarts(&events);
+            // 进程的变化也绘制为标记, 重启已经有标记
+            markers.extend(
+                events
+                    .into_iter()
+                    .filter(|(_, e)| !matches!(e.kind, EventKind::Start(_)))
+                    .map(|(x, e)| {
+                        (
+                            x,
+                            Marker {
+                                time: e.time,
+                                label: e.kind.to_string(),
+                            },
+                        )
+                    }),
+            );
+            // 段的开始也绘制为标记
+            markers.extend(segments.into_iter().map(|(x, s)| {
+                (
+                    x,
+                    Marker {
+                        time: s.time,
+                        label: format!("segment: {}", s.reason),
+                    },
+                )
+            }));
+
+            let output = |name: &str| {
+                args.out_dir
+                    .join(format!("{}.{}", name, args.format.extension()))
+            };
+
             if args.memory {
-                render_memory(&data, args.out_dir.join("memory.svg")).unwrap();
+                render_memory(
+                    &data,
+                    &markers,
+                    &theme,
+                    axes("memory"),
+                    args.format,
+                    output("memory"),
+                )
+                .unwrap();
             }
             if args.cpu {
-                render_cpu_time(&data, args.out_dir.join("cpu_time.svg")).unwrap();
-                render_cpu_usage(&data, args.out_dir.join("cpu_usage.svg")).unwrap();
+                render_cpu_time(
+                    &data,
+                    &markers,
+                    &theme,
+                    axes("cpu_time"),
+                    args.format,
+                    output("cpu_time"),
+                )
+                .unwrap();
+                render_cpu_usage(
+                    &data,
+                    &markers,
+                    args.normalise.then_some(cores),
+                    &theme,
+                    axes("cpu_usage"),
+                    args.format,
+                    output("cpu_usage"),
+                )
+                .unwrap();
+                if data.iter().any(|d| d.cores.is_some()) {
+                    render_cpu_cores(
+                        &data,
+                        &markers,
+                        cores,
+                        &theme,
+                        axes("cpu_cores"),
+                        args.format,
+                        output("cpu_cores"),
+                    )
+                    .unwrap();
+                }
             }
             if args.io {
-                render_io(&data, args.out_dir.join("io.svg")).unwrap();
+                render_io(
+                    &data,
+                    &markers,
+                    &theme,
+                    axes("io"),
+                    args.format,
+                    output("io"),
+                )
+                .unwrap();
+                if data.iter().any(|d| d.io.syscall_read.is_some()) {
+                    render_io_syscalls(
+                        &data,
+                        &markers,
+                        &theme,
+                        axes("io_syscalls"),
+                        args.format,
+                        output("io_syscalls"),
+                    )
+                    .unwrap();
+                }
+                if data.iter().any(|d| d.io_attribution.is_some()) {
+                    render_files(
+                        &data,
+                        &markers,
+                        &theme,
+                        axes("files"),
+                        args.format,
+                        output("files"),
+                    )
+                    .unwrap();
+                    render_devices(
+                        &data,
+                        &markers,
+                        &theme,
+                        axes("devices"),
+                        args.format,
+                        output("devices"),
+                    )
+                    .unwrap();
+                    for (path, mode, progress) in file_progress(&data).iter().take(TOP_IO) {
+                        log::info!(
+                            "file {} ({}): {}",
+                            path,
+                            mode,
+                            Byte::from(progress.last().copied().unwrap_or(0))
+                                .get_appropriate_unit(false)
+                        );
+                    }
+                    for (name, series) in device_io(&data).iter().take(TOP_IO) {
+                        let last = series.last().cloned().unwrap_or_default();
+                        log::info!(
+                            "device {}: read {}, written {}",
+                            name,
+                            Byte::from(last.read).get_appropriate_unit(false),
+                            Byte::from(last.written).get_appropriate_unit(false)
+                        );
+                    }
+                }
+            }
+            if args.sched {
+                render_sched(
+                    &data,
+                    &markers,
+                    &theme,
+                    axes("sched"),
+                    args.format,
+                    output("sched"),
+                )
+                .unwrap();
+            }
+            if args.fd {
+                render_fd(
+                    &data,
+                    &markers,
+                    &theme,
+                    axes("fd"),
+                    args.format,
+                    output("fd"),
+                )
+                .unwrap();
+                render_sockets(
+                    &data,
+                    &markers,
+                    &theme,
+                    axes("sockets"),
+                    args.format,
+                    output("sockets"),
+                )
+                .unwrap();
+            }
+            if args.custom {
+                for name in custom_names(&data) {
+                    // 指标名可能包含路径分隔符等
+                    let file = name
+                        .chars()
+                        .map(|c| {
+                            if c.is_ascii_alphanumeric() || c == '-' {
+                                c
+                            } else {
+                                '_'
+                            }
+                        })
+                        .collect::<String>();
+                    render_custom(
+                        &data,
+                        &markers,
+                        name,
+                        &theme,
+                        axes("custom"),
+                        args.format,
+                        output(&format!("custom-{}", file)),
+                    )
+                    .unwrap();
+                }
+            }
+            if args.pressure {
+                render_pressure(
+                    &data,
+                    &markers,
+                    &theme,
+                    axes("pressure"),
+                    args.format,
+                    output("pressure"),
+                )
+                .unwrap();
+                render_stall_time(
+                    &data,
+                    &markers,
+                    &theme,
+                    axes("stall_time"),
+                    args.format,
+                    output("stall_time"),
+                )
+                .unwrap();
+                for (name, stalled) in stall_summary(&data) {
+                    log::info!("{}: stalled {:.2}% of the time", name, stalled);
+                }
+            }
+            if let Some(plot) = &args.plot {
+                let result = render_plot(
+                    &data,
+                    &markers,
+                    &restarts,
+                    plot,
+                    &theme,
+                    axes("plot"),
+                    args.format,
+                    output("plot"),
+                );
+                if let Err(err) = result {
+                    log::error!("{}", err);
+                    std::process::exit(1);
+                }
             }
         }
     }
@@ -96,11 +648,14 @@ struct Arguments {
     sub_cmd: SubCommandEnum,
 }
 
+// 只在启动时解析一次
+#[allow(clippy::large_enum_variant)]
 #[derive(FromArgs)]
 #[argh(subcommand)]
 enum SubCommandEnum {
     Record(SubCommandRecord),
     Render(SubCommandRender),
+    Mark(SubCommandMark),
 }
 
 #[derive(FromArgs)]
@@ -119,13 +674,121 @@ struct SubCommandRecord {
     /// scanning frequency. default: 500ms
     frequency: ParseDuration,
 
+    #[argh(option)]
+    /// adaptive sampling: scan at this faster frequency while cpu usage or rss change sharply
+    burst: Option<ParseDuration>,
+
+    #[argh(option, default = "20.0")]
+    /// cpu usage change between two samples, in percentage points, that starts a burst. default: 20
+    burst_cpu: f32,
+
+    #[argh(option, default = "10.0")]
+    /// rss change between two samples, in percent, that starts a burst. default: 10
+    burst_rss: f64,
+
     #[argh(option, default = "ParseDuration(Duration::from_secs(3))")]
     /// shutdown timeout. default: 3s
     shutdown_timeout: ParseDuration,
 
+    #[argh(option, default = "StopPolicy::FirstExit")]
+    /// when to stop all recordings: `first` recording ends, `all` recordings end, or only on `signal`. default: first
+    stop_on: StopPolicy,
+
+    #[argh(option)]
+    /// stop recording after this long
+    duration: Option<ParseDuration>,
+
+    #[argh(option)]
+    /// stop recording after this many samples
+    samples: Option<u64>,
+
+    #[argh(option)]
+    /// stop recording once cpu usage has stayed below `--idle-cpu` for this long
+    until_idle: Option<ParseDuration>,
+
+    #[argh(option, default = "1.0")]
+    /// cpu usage percentage below which the process counts as idle for `--until-idle`. default: 1
+    idle_cpu: f32,
+
     #[argh(option, short = 'o', default = "PathBuf::new().join(\".\")")]
     /// output directory. default: "."
     out_dir: PathBuf,
+
+    #[argh(option)]
+    /// record the first process with this name
+    name: Vec<String>,
+
+    #[argh(option)]
+    /// record the first process whose command line contains this string
+    cmdline: Vec<String>,
+
+    #[argh(switch)]
+    /// when a process selected by `--name` or `--cmdline` exits, wait for a new one and keep recording it into the same file
+    follow_restarts: bool,
+
+    #[argh(option)]
+    /// record a whole cgroup v2 instead of a process, relative to the cgroup mount point. linux only
+    cgroup: Vec<PathBuf>,
+
+    #[argh(option)]
+    /// record the cgroup of a systemd unit. linux only
+    unit: Vec<String>,
+
+    #[argh(switch)]
+    /// record open file descriptors and sockets. linux only
+    fd: bool,
+
+    #[argh(switch)]
+    /// record system-wide and cgroup pressure stall information. linux only
+    psi: bool,
+
+    #[argh(switch)]
+    /// record system-wide cpu, load, memory, swap, disk and network as a baseline
+    host: bool,
+
+    #[argh(switch)]
+    /// record cpu affinity, last-run cpu and per-core usage. linux only
+    cores: bool,
+
+    #[argh(switch)]
+    /// record read/write positions of open files and i/o per block device. linux only
+    files: bool,
+
+    #[argh(option)]
+    /// record this environment variable of the process in the file header, a trailing `*` matches a prefix
+    env: Vec<String>,
+
+    #[argh(option)]
+    /// listen on a unix socket for markers sent by `clairvoyance mark`. unix only
+    mark_socket: Option<PathBuf>,
+
+    #[argh(option)]
+    /// trigger mode: only save samples around the moments rss exceeds this size (e.g. 1GB)
+    trigger_rss: Option<ParseByte>,
+
+    #[argh(option)]
+    /// trigger mode: only save samples around the moments cpu usage exceeds this percentage
+    trigger_cpu: Option<f32>,
+
+    #[argh(option, default = "ParseDuration(Duration::ZERO)")]
+    /// how long cpu usage has to stay above `--trigger-cpu`. default: 0s
+    trigger_cpu_for: ParseDuration,
+
+    #[argh(switch)]
+    /// trigger mode: save the samples before the process exits
+    trigger_exit: bool,
+
+    #[argh(option, default = "ParseDuration(Duration::from_secs(60))")]
+    /// trigger mode: how long before a trigger to save. default: 60s
+    before: ParseDuration,
+
+    #[argh(option, default = "ParseDuration(Duration::from_secs(60))")]
+    /// trigger mode: how long after a trigger to save. default: 60s
+    after: ParseDuration,
+
+    #[argh(option)]
+    /// listen on a udp address (e.g. 127.0.0.1:8125) for statsd-like custom metrics
+    metrics: Option<SocketAddr>,
 }
 
 #[derive(FromArgs)]
@@ -140,6 +803,46 @@ struct SubCommandRender {
     /// output directory. default: "."
     out_dir: PathBuf,
 
+    #[argh(option, default = "Format::Svg")]
+    /// image format: svg, png or pdf. default: svg
+    format: Format,
+
+    #[argh(option)]
+    /// chart theme: light, dark or a json theme file. default: light
+    theme: Option<String>,
+
+    #[argh(option)]
+    /// chart size in pixels, e.g. 1280x720. default: 1920x1080
+    size: Option<ParseSize>,
+
+    #[argh(option)]
+    /// line colors: default, okabe-ito or tol (both colorblind-safe)
+    palette: Option<Palette>,
+
+    #[argh(option)]
+    /// line width in pixels. default: 3
+    line_width: Option<u32>,
+
+    #[argh(option)]
+    /// font family of the chart text. default: sans-serif
+    font: Option<String>,
+
+    #[argh(switch)]
+    /// show bytes in decimal units (KB, MB, GB) instead of binary units (KiB, MiB, GiB)
+    decimal: bool,
+
+    #[argh(option)]
+    /// draw the y axis of this chart (e.g. memory) in log scale, can be repeated
+    log: Vec<String>,
+
+    #[argh(option)]
+    /// draw the second group of lines of this chart on a y axis on the right, can be repeated
+    secondary_axis: Vec<String>,
+
+    #[argh(option)]
+    /// draw every group of lines of this chart in its own panel, can be repeated
+    split: Vec<String>,
+
     #[argh(switch, short = 'm')]
     /// render memory result
     memory: bool,
@@ -148,15 +851,75 @@ struct SubCommandRender {
     /// render cpu result
     cpu: bool,
 
+    #[argh(switch, short = 'n')]
+    /// show cpu usage as a share of all cores of the recording machine instead of 100% per core
+    normalise: bool,
+
     #[argh(switch, short = 'i')]
     /// render io result
     io: bool,
 
+    #[argh(switch, short = 's')]
+    /// render scheduling result (context switches, page faults, run queue delay). linux only
+    sched: bool,
+
+    #[argh(switch, short = 'd')]
+    /// render file descriptor and socket result. requires `record --fd`
+    fd: bool,
+
+    #[argh(switch, short = 'p')]
+    /// render pressure stall information and print how long it was stalled. requires `record --psi`
+    pressure: bool,
+
+    #[argh(switch, short = 'u')]
+    /// render custom metrics, one chart per metric. requires `record --metrics`
+    custom: bool,
+
+    #[argh(option)]
+    /// render the given metrics on one chart, e.g. "memory.rss,rate(io.storage_written)". paths are field names of result.json, transforms are rate and delta
+    plot: Option<Plot>,
+
     #[argh(switch, short = 'j')]
     /// convert intermediate files to json format
     json: bool,
 }
 
+#[derive(FromArgs)]
+#[argh(subcommand, name = "mark")]
+/// add a marker to running recordings
+struct SubCommandMark {
+    #[argh(positional)]
+    /// marker label
+    label: String,
+
+    #[argh(option, short = 's')]
+    /// the socket given to `record --mark-socket`
+    socket: PathBuf,
+}
+
+struct ParseByte(u64);
+
+impl argh::FromArgValue for ParseByte {
+    fn from_arg_value(value: &str) -> Result<Self, String> {
+        Byte::from_str(value)
+            .map(|byte| ParseByte(byte.get_bytes()))
+            .map_err(|_| value.to_string())
+    }
+}
+
+struct ParseSize((u32, u32));
+
+impl argh::FromArgValue for ParseSize {
+    fn from_arg_value(value: &str) -> Result<Self, String> {
+        value
+            .split_once('x')
+            .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)))
+            .filter(|(width, height)| *width > 0 && *height > 0)
+            .map(ParseSize)
+            .ok_or_else(|| format!("invalid size {}, expected WIDTHxHEIGHT", value))
+    }
+}
+
 struct ParseDuration(Duration);
 
 impl argh::FromArgValue for ParseDuration {
@@ -0,0 +1,98 @@
+// 通过unix socket在记录时注入标记, 每行是一个标记的标签
+use std::path::Path;
+
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::broadcast;
+
+use crate::backoff::Backoff;
+use crate::data::Marker;
+
+// 在`path`上监听, 收到的标记发送给全部Monitor
+pub fn listen(path: &Path, sender: broadcast::Sender<Marker>) -> anyhow::Result<()> {
+    // 上一次记录没有正常退出时留下的socket文件
+    if path.exists() {
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+
+    tokio::spawn(async move {
+        let mut backoff = Backoff::default();
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => {
+                    backoff.reset();
+                    stream
+                }
+                Err(err) => {
+                    let delay = backoff.next_delay();
+                    log::error!(
+                        "failed to accept marker connection: {}, retrying in {:?}",
+                        err,
+                        delay
+                    );
+                    tokio::time::sleep(delay).await;
+                    continue;
+                }
+            };
+            let sender = sender.clone();
+            tokio::spawn(async move {
+                let mut lines = BufReader::new(stream).lines();
+                while let Ok(Some(label)) = lines.next_line().await {
+                    let label = label.trim();
+                    if label.is_empty() {
+                        continue;
+                    }
+                    log::info!("marker: {}", label);
+                    // 没有正在记录的Monitor时忽略
+                    let _ = sender.send(Marker {
+                        time: chrono::Utc::now().timestamp_millis() as u64,
+                        label: label.to_owned(),
+                    });
+                }
+            });
+        }
+    });
+
+    Ok(())
+}
+
+// 发送一个标记给正在`path`上监听的record
+pub async fn send(path: &Path, label: &str) -> anyhow::Result<()> {
+    let mut stream = UnixStream::connect(path).await.map_err(|err| {
+        anyhow::anyhow!(
+            "failed to connect to {}: {}, is `record --mark-socket` running?",
+            path.display(),
+            err
+        )
+    })?;
+    // 标签不能跨行
+    stream
+        .write_all(format!("{}\n", label.replace('\n', " ")).as_bytes())
+        .await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn send_markers() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("marker.sock");
+        let (sender, mut receiver) = broadcast::channel(4);
+        listen(&path, sender).unwrap();
+
+        send(&path, "load test\nstarted").await.unwrap();
+        let marker = tokio::time::timeout(Duration::from_secs(5), receiver.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        // 换行替换为空格
+        assert_eq!(marker.label, "load test started");
+    }
+}
@@ -0,0 +1,245 @@
+// 应用程序通过udp发送的自定义指标, 类似statsd的行协议:
+// `<name>:<value>|<g|c>[|@<sample rate>][|#pid:<pid>]`, 一个数据包中可以有多行
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+
+use heim::process::Pid;
+use tokio::net::UdpSocket;
+use tokio::sync::broadcast;
+
+use crate::backoff::Backoff;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetricKind {
+    // 保持最后一次的值
+    Gauge,
+    // 每个采样之间的累加值
+    Counter,
+}
+
+#[derive(Debug, Clone)]
+pub struct Metric {
+    // 没有pid标签时发送给全部记录
+    pub pid: Option<Pid>,
+    pub name: String,
+    pub value: f64,
+    pub kind: MetricKind,
+}
+
+pub fn parse(line: &str) -> Option<Metric> {
+    let mut fields = line.trim().split('|');
+    let (name, value) = fields.next()?.rsplit_once(':')?;
+    let mut value = value.parse::<f64>().ok()?;
+    let kind = match fields.next()? {
+        "g" => MetricKind::Gauge,
+        "c" => MetricKind::Counter,
+        _ => return None,
+    };
+
+    let mut pid = None;
+    for field in fields {
+        if let Some(rate) = field.strip_prefix('@') {
+            let rate = rate.parse::<f64>().ok().filter(|rate| *rate > 0.0)?;
+            if kind == MetricKind::Counter {
+                value /= rate;
+            }
+        } else if let Some(tags) = field.strip_prefix('#') {
+            pid = tags
+                .split(',')
+                .find_map(|tag| tag.strip_prefix("pid:"))
+                .and_then(|p| p.parse().ok());
+        }
+    }
+
+    // inf, NaN和超出f64范围的值(包括除以很小的采样率之后)无法绘制
+    if name.is_empty() || !value.is_finite() {
+        return None;
+    }
+    Some(Metric {
+        pid,
+        name: name.to_owned(),
+        value,
+        kind,
+    })
+}
+
+// 在`addr`上接收指标, 发送给全部Monitor. 返回实际监听的地址
+pub async fn listen(
+    addr: SocketAddr,
+    sender: broadcast::Sender<Metric>,
+) -> anyhow::Result<SocketAddr> {
+    let socket = UdpSocket::bind(addr).await?;
+    let local_addr = socket.local_addr()?;
+
+    tokio::spawn(async move {
+        let mut buf = vec![0u8; 65536];
+        let mut backoff = Backoff::default();
+        loop {
+            let len = match socket.recv(&mut buf).await {
+                Ok(len) => {
+                    backoff.reset();
+                    len
+                }
+                Err(err) => {
+                    let delay = backoff.next_delay();
+                    log::error!(
+                        "failed to receive metrics: {}, retrying in {:?}",
+                        err,
+                        delay
+                    );
+                    tokio::time::sleep(delay).await;
+                    continue;
+                }
+            };
+            for line in String::from_utf8_lossy(&buf[..len]).lines() {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                match parse(line) {
+                    // 没有正在记录的Monitor时忽略
+                    Some(metric) => {
+                        let _ = sender.send(metric);
+                    }
+                    None => log::warn!("invalid metric: {}", line),
+                }
+            }
+        }
+    });
+
+    Ok(local_addr)
+}
+
+// 一个Monitor在两次采样之间收到的指标
+#[derive(Debug, Default)]
+pub struct CustomMetrics {
+    gauges: BTreeMap<String, f64>,
+    counters: BTreeMap<String, f64>,
+}
+
+impl CustomMetrics {
+    pub fn add(&mut self, metric: Metric) {
+        match metric.kind {
+            MetricKind::Gauge => {
+                self.gauges.insert(metric.name, metric.value);
+            }
+            MetricKind::Counter => {
+                *self.counters.entry(metric.name).or_insert(0.0) += metric.value;
+            }
+        }
+    }
+
+    // 本次采样的值. 计数器清零, 之后没有收到时记录为0
+    pub fn sample(&mut self) -> Option<BTreeMap<String, f64>> {
+        if self.gauges.is_empty() && self.counters.is_empty() {
+            return None;
+        }
+        let mut sample = self.gauges.clone();
+        for (name, value) in &mut self.counters {
+            sample.insert(name.clone(), *value);
+            *value = 0.0;
+        }
+        Some(sample)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn gauge_and_counter() {
+        let metric = parse("queue.depth:42|g").unwrap();
+        assert_eq!(metric.name, "queue.depth");
+        assert_eq!(metric.value, 42.0);
+        assert_eq!(metric.kind, MetricKind::Gauge);
+        assert_eq!(metric.pid, None);
+
+        let metric = parse("  requests:3|c|#pid:1234,host:a  ").unwrap();
+        assert_eq!(metric.name, "requests");
+        assert_eq!(metric.kind, MetricKind::Counter);
+        assert_eq!(metric.pid, Some(1234));
+    }
+
+    #[test]
+    fn sample_rate() {
+        // 计数器按采样率放大, 仪表不变
+        assert_eq!(parse("hits:1|c|@0.1").unwrap().value, 10.0);
+        assert_eq!(parse("size:1|g|@0.1").unwrap().value, 1.0);
+        assert!(parse("hits:1|c|@0").is_none());
+        assert!(parse("hits:1|c|@x").is_none());
+    }
+
+    #[test]
+    fn names() {
+        // 名称可以包含`.`, 空格和`:`, 值是最后一个`:`之后的部分
+        assert_eq!(parse("cache size:7|g").unwrap().name, "cache size");
+        assert_eq!(parse("db:pool.idle:2.5|g").unwrap().name, "db:pool.idle");
+        assert!(parse(":1|g").is_none());
+    }
+
+    #[test]
+    fn invalid_lines() {
+        assert!(parse("depth").is_none());
+        assert!(parse("depth:42").is_none());
+        assert!(parse("depth:abc|g").is_none());
+        assert!(parse("depth:42|h").is_none());
+        assert!(parse("").is_none());
+        assert!(parse("x:inf|g").is_none());
+        assert!(parse("x:-inf|g").is_none());
+        assert!(parse("x:NaN|g").is_none());
+        assert!(parse("x:nan|c").is_none());
+        assert!(parse("x:1e400|g").is_none());
+        assert!(parse("x:1e300|c|@1e-320").is_none());
+    }
+
+    #[test]
+    fn sample_resets_counters() {
+        let mut custom = CustomMetrics::default();
+        assert!(custom.sample().is_none());
+
+        for line in ["size:1|g", "size:5|g", "hits:2|c", "hits:3|c"] {
+            custom.add(parse(line).unwrap());
+        }
+        let sample = custom.sample().unwrap();
+        assert_eq!(sample["size"], 5.0);
+        assert_eq!(sample["hits"], 5.0);
+
+        // 仪表保持最后的值, 计数器没有收到时为0
+        let sample = custom.sample().unwrap();
+        assert_eq!(sample["size"], 5.0);
+        assert_eq!(sample["hits"], 0.0);
+    }
+
+    #[tokio::test]
+    async fn receive_datagrams() {
+        let (sender, mut receiver) = broadcast::channel(16);
+        let addr = listen("127.0.0.1:0".parse().unwrap(), sender)
+            .await
+            .unwrap();
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client
+            .send_to(b"queue.depth:42|g|#pid:7\nbroken\n\nhits:2|c\n", addr)
+            .await
+            .unwrap();
+        client.send_to(b"hits:3|c", addr).await.unwrap();
+
+        let mut custom = CustomMetrics::default();
+        let mut pids = Vec::new();
+        for _ in 0..3 {
+            let metric = tokio::time::timeout(Duration::from_secs(5), receiver.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            pids.push(metric.pid);
+            custom.add(metric);
+        }
+        assert_eq!(pids, [Some(7), None, None]);
+
+        let sample = custom.sample().unwrap();
+        assert_eq!(sample["queue.depth"], 42.0);
+        assert_eq!(sample["hits"], 5.0);
+    }
+}
@@ -1,155 +1,986 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Display, Formatter};
 use std::path::Path;
-use std::time::Duration;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
 
 use futures::stream::StreamExt;
-use heim::process::{CpuUsage as HeimCpuUsage, Pid, Process};
+use heim::process::{CpuUsage as HeimCpuUsage, Pid, Process, ProcessError, Status};
+use tokio::sync::broadcast;
 
-use crate::data::{CpuTime, CpuUsage, Data, Io, Memory};
-use crate::shutdown_notify::ShutdownGuard;
-use crate::store::StoreStream;
+use crate::data::{
+    CollectError, CpuTime, CpuUsage, Data, Event, EventKind, Header, Io, Limits, Marker, Memory,
+    ProcessInfo, Record,
+};
+#[cfg(target_os = "linux")]
+use crate::data::{CpuCores, IoAttribution, Psi};
+use crate::host::HostCollector;
+use crate::metrics::{CustomMetrics, Metric};
+use crate::store::{StoreStream, VERSION};
+use crate::supervisor::{ExitReason, ShutdownGuard};
+use crate::trigger::{Trigger, TriggerBuffer};
+
+// 可选的采集项, 默认都不采集
+#[derive(Debug, Clone, Default)]
+pub struct Collectors {
+    // 打开的文件描述符和socket. linux only
+    pub fd: bool,
+    // 系统和进程所在cgroup的Pressure Stall Information. linux only
+    pub psi: bool,
+    // 系统整体的cpu, 内存, 磁盘和网络
+    pub host: bool,
+    // cpu亲和性和每个核上的使用率. linux only
+    pub cores: bool,
+    // 每个文件的读写位置和每个块设备的读写. linux only
+    pub files: bool,
+    // 记录到文件头中的环境变量, 以`*`结尾时匹配前缀
+    pub env: Vec<String>,
+}
+
+impl Collectors {
+    fn env_allowed(&self, name: &str) -> bool {
+        self.env
+            .iter()
+            .any(|pattern| match pattern.strip_suffix('*') {
+                Some(prefix) => name.starts_with(prefix),
+                None => pattern == name,
+            })
+    }
+}
+
+// 自适应采样: 指标剧烈变化时切换到快速采样, 在一个正常采样间隔内都稳定后恢复
+#[derive(Debug, Clone, Copy)]
+pub struct Adaptive {
+    // 快速采样的间隔
+    pub fast: Duration,
+    // 与上一次采样相比cpu使用率变化的百分点
+    pub cpu_threshold: f32,
+    // 与上一次采样相比rss变化的百分比
+    pub rss_threshold: f64,
+}
+
+impl Adaptive {
+    fn is_burst(&self, last: &Data, now: &Data) -> bool {
+        let cpu = (now.cpu_usage.0 - last.cpu_usage.0).abs();
+        let rss =
+            now.memory.rss.abs_diff(last.memory.rss) as f64 / last.memory.rss.max(1) as f64 * 100.0;
+        cpu > self.cpu_threshold || rss > self.rss_threshold
+    }
+}
+
+// 记录自己结束的条件. 一个记录结束后全部记录都会结束
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Stop {
+    // 采样次数
+    pub samples: Option<u64>,
+    // cpu使用率持续低于`idle_cpu`这么长时间
+    pub idle_for: Option<Duration>,
+    pub idle_cpu: f32,
+}
+
+impl Stop {
+    fn reached_samples(&self, samples: u64) -> bool {
+        self.samples.is_some_and(|max| samples >= max)
+    }
+
+    // cpu使用率是否已经持续低于`idle_cpu`达到`idle_for`, `idle_since`为开始低于的时间.
+    // 没有上一次的cpu读数时`cpu_usage`为None(使用率总是0), 不检查
+    fn is_idle(
+        &self,
+        idle_since: &mut Option<Instant>,
+        cpu_usage: Option<f32>,
+        now: Instant,
+    ) -> bool {
+        match (self.idle_for, cpu_usage) {
+            (Some(idle_for), Some(cpu_usage)) if cpu_usage < self.idle_cpu => {
+                now.duration_since(*idle_since.get_or_insert(now)) >= idle_for
+            }
+            (Some(_), Some(_)) => {
+                *idle_since = None;
+                false
+            }
+            _ => false,
+        }
+    }
+}
+
+// 按名字或命令行选择进程, 进程重启后可以再次找到
+#[derive(Debug, Clone)]
+pub enum Selector {
+    Name(String),
+    // 命令行中包含这个字符串
+    Cmdline(String),
+}
+
+impl Selector {
+    async fn matches(&self, process: &Process) -> bool {
+        match self {
+            Selector::Name(name) => process.name().await.is_ok_and(|n| &n == name),
+            Selector::Cmdline(pattern) => process.command().await.is_ok_and(|command| {
+                command
+                    .to_os_string()
+                    .to_string_lossy()
+                    .contains(pattern.as_str())
+            }),
+        }
+    }
+
+    // 跳过自己, 僵尸进程和`exclude`. 遍历时已经退出或者没有权限读取的进程也跳过
+    async fn find(&self, exclude: Option<&Process>) -> anyhow::Result<Option<Process>> {
+        let processes = heim::process::processes().await?;
+        pin_utils::pin_mut!(processes);
+        while let Some(process) = processes.next().await {
+            let process = match process {
+                Ok(process) => process,
+                Err(_) => continue,
+            };
+            if process.pid() as u32 == std::process::id()
+                || exclude == Some(&process)
+                || matches!(process.status().await, Ok(Status::Zombie))
+            {
+                continue;
+            }
+            if self.matches(&process).await {
+                return Ok(Some(process));
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl Display for Selector {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Selector::Name(name) => write!(f, "process named {}", name),
+            Selector::Cmdline(pattern) => write!(f, "process with {} in its command line", pattern),
+        }
+    }
+}
+
+async fn command_line(process: &Process) -> Option<Vec<String>> {
+    let command = process.command().await.ok()?;
+    Some(
+        (&command)
+            .into_iter()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect(),
+    )
+}
+
+async fn niceness(process: &Process) -> Option<i32> {
+    cfg_if::cfg_if! {
+        if #[cfg(unix)] {
+            use heim::process::os::unix::ProcessExt;
+
+            process.niceness().await.ok()
+        } else {
+            let _ = process;
+            None
+        }
+    }
+}
+
+// 读取失败的项为None
+async fn process_info(process: &Process, collectors: &Collectors) -> ProcessInfo {
+    let mut info = ProcessInfo {
+        pid: process.pid() as u32,
+        exe: process
+            .exe()
+            .await
+            .ok()
+            .map(|exe| exe.display().to_string()),
+        cmdline: command_line(process).await.unwrap_or_default(),
+        cwd: process
+            .cwd()
+            .await
+            .ok()
+            .map(|cwd| cwd.display().to_string()),
+        ppid: process.parent_pid().await.ok().map(|ppid| ppid as u32),
+        start_time: process
+            .create_time()
+            .await
+            .ok()
+            .map(|time| time.get::<heim::units::time::millisecond>() as u64),
+        nice: niceness(process).await,
+        ..ProcessInfo::default()
+    };
+
+    if !collectors.env.is_empty() {
+        if let Ok(environment) = process.environment().await {
+            info.env = environment
+                .iter()
+                .map(|(name, value)| (name.to_string_lossy(), value.to_string_lossy()))
+                .filter(|(name, _)| collectors.env_allowed(name))
+                .map(|(name, value)| (name.into_owned(), value.into_owned()))
+                .collect();
+        }
+    }
+
+    cfg_if::cfg_if! {
+        if #[cfg(target_os = "linux")] {
+            if let Ok((uid, gid)) = crate::procfs::owner(process.pid()).await {
+                info.uid = Some(uid);
+                info.user = crate::procfs::account_name("/etc/passwd", uid).await;
+                info.gid = Some(gid);
+                info.group = crate::procfs::account_name("/etc/group", gid).await;
+            }
+        }
+    }
+
+    info
+}
+
+// 一次采样中失败的采集项
+type Errors = Vec<(&'static str, anyhow::Error)>;
+
+// 可选的采集项失败时这次采样中没有值, 错误由调用者记录
+fn optional<T, E>(errors: &mut Errors, collector: &'static str, result: Result<T, E>) -> Option<T>
+where
+    E: Into<anyhow::Error>,
+{
+    result
+        .map_err(|err| errors.push((collector, err.into())))
+        .ok()
+}
+
+// 进程已经退出, 不需要再重试
+fn is_gone(err: &anyhow::Error) -> bool {
+    matches!(
+        err.downcast_ref::<ProcessError>(),
+        Some(ProcessError::NoSuchProcess(_) | ProcessError::ZombieProcess(_))
+    )
+}
+
+// 记录的对象
+enum Target {
+    Process {
+        process: Process,
+        last_cpu_usage: Option<HeimCpuUsage>,
+        // 上一次采样时每个线程的cpu时间, 用来估算每个核上的使用率
+        last_threads: Option<(HashMap<Pid, (u64, u32)>, Instant)>,
+    },
+    // linux only
+    #[cfg(target_os = "linux")]
+    Cgroup {
+        // cgroup v2的挂载点
+        root: &'static Path,
+        dir: PathBuf,
+        // 上一次采样时cpu.stat的usage_usec
+        last_usage: Option<(u64, Instant)>,
+    },
+}
+
+impl Target {
+    // cgroup只接收没有pid标签的指标
+    fn has_pid(&self, pid: Pid) -> bool {
+        match self {
+            Target::Process { process, .. } => process.pid() == pid,
+            #[cfg(target_os = "linux")]
+            Target::Cgroup { .. } => false,
+        }
+    }
+}
+
+impl Display for Target {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Target::Process { process, .. } => write!(f, "{}", process.pid()),
+            #[cfg(target_os = "linux")]
+            Target::Cgroup { root, dir, .. } => {
+                write!(
+                    f,
+                    "cgroup /{}",
+                    dir.strip_prefix(root).unwrap_or(dir).display()
+                )
+            }
+        }
+    }
+}
 
 pub struct Monitor {
-    process: Process,
-    last_cpu_usage: Option<HeimCpuUsage>,
+    target: Target,
+    // 中间文件的名字和目录, 轮换时使用
+    name: String,
+    out_dir: PathBuf,
+    collectors: Collectors,
+    host: Option<HostCollector>,
+    last_limits: Option<Limits>,
+    // 当前记录的进程的信息, 用来发现exec和nice值的变化
+    info: Option<ProcessInfo>,
+    markers: Option<broadcast::Receiver<Marker>>,
+    rotate: Option<broadcast::Receiver<()>>,
+    metrics: Option<broadcast::Receiver<Metric>>,
+    custom: CustomMetrics,
+    adaptive: Option<Adaptive>,
+    trigger: Option<TriggerBuffer>,
+    stop: Stop,
+    // 选择进程的条件, 只有按条件选择的记录才能跟随重启
+    selector: Option<Selector>,
+    follow_restarts: bool,
+    // 每个采集项失败的次数和最后一次的错误
+    errors: BTreeMap<&'static str, (u64, String)>,
+    // 采样的次数, 包括失败的
+    collections: u64,
     store_stream: StoreStream,
-    _shutdown_guard: ShutdownGuard,
+    shutdown_guard: ShutdownGuard,
 }
 
 impl Monitor {
     pub async fn new<P>(
         process: Process,
         out_dir: P,
-        _shutdown_guard: ShutdownGuard,
+        collectors: Collectors,
+        shutdown_guard: ShutdownGuard,
     ) -> anyhow::Result<Self>
     where
         P: AsRef<Path>,
     {
-        let store_stream = StoreStream::create(out_dir.as_ref().join(format!(
-            "{}-{}.clairvoyance",
-            process.pid(),
-            chrono::Local::now().format("%F_%H-%M-%S")
-        )))
-        .await?;
-
-        Ok(Monitor {
+        let name = process.pid().to_string();
+        let target = Target::Process {
             process,
             last_cpu_usage: None,
+            last_threads: None,
+        };
+        Monitor::create(target, &name, out_dir, collectors, shutdown_guard).await
+    }
+
+    async fn create<P>(
+        target: Target,
+        name: &str,
+        out_dir: P,
+        collectors: Collectors,
+        shutdown_guard: ShutdownGuard,
+    ) -> anyhow::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let info = match &target {
+            Target::Process { process, .. } => Some(process_info(process, &collectors).await),
+            #[cfg(target_os = "linux")]
+            Target::Cgroup { .. } => None,
+        };
+        let store_stream = Monitor::create_store(name, out_dir.as_ref(), info.clone()).await?;
+
+        Ok(Monitor {
+            target,
+            name: name.to_owned(),
+            out_dir: out_dir.as_ref().to_owned(),
+            host: collectors.host.then(HostCollector::new),
+            collectors,
+            last_limits: None,
+            info,
+            markers: None,
+            rotate: None,
+            metrics: None,
+            custom: CustomMetrics::default(),
+            adaptive: None,
+            trigger: None,
+            stop: Stop::default(),
+            selector: None,
+            follow_restarts: false,
+            errors: BTreeMap::new(),
+            collections: 0,
             store_stream,
-            _shutdown_guard,
+            shutdown_guard,
         })
     }
 
+    // 中间文件以记录对象和开始时间命名, 同一秒内轮换时加上序号
+    async fn create_store(
+        name: &str,
+        out_dir: &Path,
+        process: Option<ProcessInfo>,
+    ) -> anyhow::Result<StoreStream> {
+        let time = chrono::Local::now().format("%F_%H-%M-%S");
+        let mut path = out_dir.join(format!("{}-{}.clairvoyance", name, time));
+        let mut n = 1;
+        while tokio::fs::metadata(&path).await.is_ok() {
+            path = out_dir.join(format!("{}-{}-{}.clairvoyance", name, time, n));
+            n += 1;
+        }
+
+        StoreStream::create(
+            path,
+            Header {
+                version: VERSION,
+                cores: num_cpus::get() as u32,
+                process,
+            },
+        )
+        .await
+    }
+
+    // 结束当前的中间文件, 之后的记录写入新的文件
+    async fn rotate(&mut self) -> anyhow::Result<()> {
+        self.store_stream.flush().await?;
+        self.store_stream =
+            Monitor::create_store(&self.name, &self.out_dir, self.info.clone()).await?;
+        // 新的文件也需要从第一次采样开始记录limits
+        self.last_limits = None;
+        log::info!("{} rotated to a new file", self.target);
+        Ok(())
+    }
+
+    // 记录整个cgroup v2, `path`可以是挂载点下的完整路径或/proc/<pid>/cgroup中的路径
+    #[cfg(target_os = "linux")]
+    pub async fn from_cgroup<P>(
+        path: &Path,
+        out_dir: P,
+        collectors: Collectors,
+        shutdown_guard: ShutdownGuard,
+    ) -> anyhow::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let root = crate::cgroup::mount_point().await;
+        let dir = crate::cgroup::resolve(root, path)?;
+        if !tokio::fs::metadata(&dir).await?.is_dir() {
+            anyhow::bail!("{} is not a cgroup", dir.display());
+        }
+
+        let name = format!(
+            "cgroup-{}",
+            dir.file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_else(|| "root".to_owned())
+        );
+        let target = Target::Cgroup {
+            root,
+            dir,
+            last_usage: None,
+        };
+        Monitor::create(target, &name, out_dir, collectors, shutdown_guard).await
+    }
+
+    // 记录systemd unit所在的cgroup
+    #[cfg(target_os = "linux")]
+    pub async fn from_unit<P>(
+        unit: &str,
+        out_dir: P,
+        collectors: Collectors,
+        shutdown_guard: ShutdownGuard,
+    ) -> anyhow::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let root = crate::cgroup::mount_point().await;
+        let dir = crate::cgroup::unit_cgroup(root, unit).await?;
+        Monitor::from_cgroup(&dir, out_dir, collectors, shutdown_guard).await
+    }
+
     pub async fn from_pid<P>(
         pid: Pid,
         out_dir: P,
-        _shutdown_guard: ShutdownGuard,
+        collectors: Collectors,
+        shutdown_guard: ShutdownGuard,
     ) -> anyhow::Result<Self>
     where
         P: AsRef<Path>,
     {
         let process = heim::process::get(pid).await?;
-        Monitor::new(process, out_dir, _shutdown_guard).await
+        Monitor::new(process, out_dir, collectors, shutdown_guard).await
     }
 
-    // todo:
-    pub async fn from_name<P>(
-        name: &str,
+    // 记录第一个满足条件的进程
+    pub async fn from_selector<P>(
+        selector: Selector,
         out_dir: P,
-        _shutdown_guard: ShutdownGuard,
+        collectors: Collectors,
+        shutdown_guard: ShutdownGuard,
     ) -> anyhow::Result<Self>
     where
         P: AsRef<Path>,
     {
-        let processes = heim::process::processes().await?;
-        pin_utils::pin_mut!(processes);
-        let mut process = None;
-        while let Some(Ok(p)) = processes.next().await {
-            if let Ok(proc_name) = p.name().await {
-                if proc_name == name {
-                    process = Some(p);
-                    break;
-                }
+        match selector.find(None).await? {
+            Some(process) => {
+                let mut monitor =
+                    Monitor::new(process, out_dir, collectors, shutdown_guard).await?;
+                monitor.selector = Some(selector);
+                Ok(monitor)
             }
+            None => anyhow::bail!("no {} was found", selector),
         }
+    }
 
-        if let Some(process) = process {
-            Monitor::new(process, out_dir, _shutdown_guard).await
-        } else {
-            anyhow::bail!("no process named {} was found", name)
+    // 收到的标记会写入中间文件
+    pub fn subscribe_markers(&mut self, receiver: broadcast::Receiver<Marker>) {
+        self.markers = Some(receiver);
+    }
+
+    // 收到时轮换中间文件, 见`rotate`
+    pub fn subscribe_rotate(&mut self, receiver: broadcast::Receiver<()>) {
+        self.rotate = Some(receiver);
+    }
+
+    // 发送给这个记录的自定义指标会附加到下一次采样
+    pub fn subscribe_metrics(&mut self, receiver: broadcast::Receiver<Metric>) {
+        self.metrics = Some(receiver);
+    }
+
+    pub fn set_adaptive(&mut self, adaptive: Adaptive) {
+        self.adaptive = Some(adaptive);
+    }
+
+    // 只在满足条件时保存前后一段时间的记录
+    pub fn set_trigger(&mut self, trigger: Trigger) {
+        self.trigger = Some(TriggerBuffer::new(trigger));
+    }
+
+    pub fn set_stop(&mut self, stop: Stop) {
+        self.stop = stop;
+    }
+
+    // 进程退出后等待新的满足条件的进程, 在同一个中间文件中继续记录
+    pub fn set_follow_restarts(&mut self, follow_restarts: bool) {
+        self.follow_restarts = follow_restarts;
+    }
+
+    // 触发模式下先放入缓冲区
+    async fn persist(&mut self, record: Record) -> anyhow::Result<()> {
+        let record = match &mut self.trigger {
+            Some(trigger) => match trigger.buffer(record) {
+                Some(record) => record,
+                None => return Ok(()),
+            },
+            None => record,
+        };
+        self.store_stream.write(&record).await
+    }
+
+    async fn fire(&mut self, reason: String) -> anyhow::Result<()> {
+        if let Some(trigger) = &mut self.trigger {
+            for record in trigger.fire(reason) {
+                self.store_stream.write(&record).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn fire_on_exit(&mut self) -> anyhow::Result<()> {
+        if self
+            .trigger
+            .as_ref()
+            .is_some_and(|trigger| trigger.trigger().exit)
+        {
+            self.fire(format!("{} exited", self.target)).await?;
+        }
+        Ok(())
+    }
+
+    // 找到新的进程时切换过去, 并写入重启的标记
+    async fn restart(&mut self) -> anyhow::Result<bool> {
+        let (selector, old) = match (&self.selector, &self.target) {
+            (Some(selector), Target::Process { process, .. }) => (selector, process),
+            _ => return Ok(false),
+        };
+        let process = match selector.find(Some(old)).await {
+            Ok(Some(process)) => process,
+            Ok(None) => return Ok(false),
+            Err(err) => {
+                log::warn!("failed to look for a new {}: {:?}", selector, err);
+                return Ok(false);
+            }
+        };
+
+        let label = format!("restart: {} -> {}", old.pid(), process.pid());
+        log::info!("{} restarted as {}", self.target, process.pid());
+        let info = process_info(&process, &self.collectors).await;
+        self.info = Some(info.clone());
+        self.target = Target::Process {
+            process,
+            last_cpu_usage: None,
+            last_threads: None,
+        };
+        self.last_limits = None;
+        self.persist(Record::Marker(Marker {
+            time: chrono::Utc::now().timestamp_millis() as u64,
+            label,
+        }))
+        .await?;
+        self.event(EventKind::Start(info)).await?;
+        Ok(true)
+    }
+
+    async fn event(&mut self, kind: EventKind) -> anyhow::Result<()> {
+        log::info!("{}: {}", self.target, kind);
+        self.persist(Record::Event(Event {
+            time: chrono::Utc::now().timestamp_millis() as u64,
+            kind,
+        }))
+        .await
+    }
+
+    // exec和nice值的变化, 命令行变化就认为执行了新的程序
+    async fn check_lifecycle(&mut self) -> anyhow::Result<()> {
+        let (process, info) = match (&self.target, &mut self.info) {
+            (Target::Process { process, .. }, Some(info)) => (process, info),
+            _ => return Ok(()),
+        };
+
+        let mut events = Vec::new();
+        // 退出中的进程和内核线程的命令行是空的, 不是exec
+        if let Some(cmdline) = command_line(process)
+            .await
+            .filter(|cmdline| !cmdline.is_empty())
+        {
+            if cmdline != info.cmdline {
+                info.exe = process
+                    .exe()
+                    .await
+                    .ok()
+                    .map(|exe| exe.display().to_string());
+                info.cmdline = cmdline.clone();
+                events.push(EventKind::Exec {
+                    exe: info.exe.clone(),
+                    cmdline,
+                });
+            }
+        }
+        if let Some(nice) = niceness(process).await {
+            if info.nice != Some(nice) {
+                info.nice = Some(nice);
+                events.push(EventKind::Priority { nice });
+            }
+        }
+
+        for kind in events {
+            self.event(kind).await?;
+        }
+        Ok(())
+    }
+
+    // 进程还没有被回收时才能读取退出状态
+    async fn exit_event(&mut self) -> anyhow::Result<()> {
+        let process = match &self.target {
+            Target::Process { process, .. } => process,
+            #[cfg(target_os = "linux")]
+            Target::Cgroup { .. } => return Ok(()),
+        };
+
+        cfg_if::cfg_if! {
+            if #[cfg(target_os = "linux")] {
+                // pid可能已经被其他进程重用
+                let (code, signal) = match heim::process::get(process.pid()).await {
+                    Ok(other) if other == *process => {
+                        crate::procfs::exit_status(process.pid()).await.unwrap_or_default()
+                    }
+                    _ => (None, None),
+                };
+            } else {
+                let _ = process;
+                let (code, signal) = (None, None);
+            }
+        }
+        self.event(EventKind::Exit { code, signal }).await
+    }
+
+    // 计数并写入中间文件, 每一项只在第一次失败时输出日志
+    async fn record_error(
+        &mut self,
+        collector: &'static str,
+        err: anyhow::Error,
+    ) -> anyhow::Result<()> {
+        let message = format!("{:#}", err);
+        let (count, last) = self.errors.entry(collector).or_default();
+        if *count == 0 {
+            log::warn!(
+                "{}: failed to collect {}: {}",
+                self.target,
+                collector,
+                message
+            );
         }
+        *count += 1;
+        *last = message.clone();
+        self.persist(Record::Error(CollectError {
+            time: chrono::Utc::now().timestamp_millis() as u64,
+            collector: collector.to_owned(),
+            message,
+        }))
+        .await
     }
 
+    // 一直失败的通常是没有权限或者内核不支持
+    fn report_errors(&self) {
+        for (level, message) in self.error_report() {
+            log::log!(level, "{}: {}", self.target, message);
+        }
+    }
+
+    // 每个失败的采集项的汇总, 每次采样都失败的是错误, 部分失败的是警告
+    fn error_report(&self) -> Vec<(log::Level, String)> {
+        self.errors
+            .iter()
+            .map(|(collector, (count, last))| {
+                if *count >= self.collections {
+                    (
+                        log::Level::Error,
+                        format!("{} failed in every sample: {}", collector, last),
+                    )
+                } else {
+                    (
+                        log::Level::Warn,
+                        format!(
+                            "{} failed in {}/{} samples, last error: {}",
+                            collector, count, self.collections, last
+                        ),
+                    )
+                }
+            })
+            .collect()
+    }
+
+    // 没有订阅时永远不会完成
+    async fn next<T: Clone>(receiver: &mut Option<broadcast::Receiver<T>>) -> T {
+        loop {
+            match receiver {
+                Some(r) => match r.recv().await {
+                    Ok(value) => return value,
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        log::warn!("{} messages were dropped", n);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => *receiver = None,
+                },
+                None => futures::future::pending().await,
+            }
+        }
+    }
+
+    // 采集失败不会结束记录, 写入中间文件失败时结束
     pub async fn run(&mut self, frequency: Duration) {
+        let mut reason = match self.record(frequency).await {
+            Ok(reason) => reason,
+            Err(err) => {
+                log::error!(
+                    "{}: failed to write to the store stream: {:?}",
+                    self.target,
+                    err
+                );
+                ExitReason::Store(format!("{:#}", err))
+            }
+        };
+
+        log::info!("stopping recording {}", self.target);
+        if let Err(err) = self.store_stream.flush().await {
+            log::error!(
+                "{}: failed to flush the store stream: {:?}",
+                self.target,
+                err
+            );
+            reason = ExitReason::Store(format!("{:#}", err));
+        }
+        self.report_errors();
+        self.shutdown_guard.report(self.target.to_string(), reason);
+    }
+
+    async fn record(&mut self, frequency: Duration) -> anyhow::Result<ExitReason> {
         let mut interval = tokio::time::interval(frequency);
         let mut tick = 0;
-        loop {
+        // 上一次采样, 以及快速采样时最后一次剧烈变化的时间
+        let mut last: Option<Data> = None;
+        let mut burst: Option<Instant> = None;
+        let mut samples = 0;
+        // cpu使用率开始低于`idle_cpu`的时间
+        let mut idle_since: Option<Instant> = None;
+        // 进程已经退出, 等待重启
+        let mut waiting = false;
+        let reason = loop {
             tokio::select! {
                 biased;
 
-                _ = self._shutdown_guard.notified() => {
-                    break
+                _ = self.shutdown_guard.notified() => {
+                    break self.shutdown_guard.stop_reason()
+                }
+
+                metric = Monitor::next(&mut self.metrics) => {
+                    if metric.pid.is_none_or(|pid| self.target.has_pid(pid)) {
+                        self.custom.add(metric);
+                    }
+                }
+
+                _ = Monitor::next(&mut self.rotate) => {
+                    self.rotate().await?;
+                }
+
+                marker = Monitor::next(&mut self.markers) => {
+                    self.persist(Record::Marker(marker)).await?;
                 }
 
                 _ = interval.tick() => {
                     tick += 1;
                     if tick % 3 == 0 {
-                        self.store_stream.flush().await.expect("an error occurred while flushing to the store stream");
+                        self.store_stream.flush().await?;
                     }
 
-                    if !self.process.is_running().await.unwrap_or(false) {
-                        break
+                    if waiting {
+                        if !self.restart().await? {
+                            continue;
+                        }
+                        waiting = false;
+                        last = None;
+                        idle_since = None;
                     }
 
-                    match self.collect().await {
-                        Err(err) => {
-                            log::error!("an error occurred during collection: {:?}", err);
-                            break
+                    // 先检查pid是否还属于原来的进程, 被新的进程重用时不能采样,
+                    // 否则cpu基准和自定义指标会被新进程的数据更新
+                    let result = if self.is_running().await {
+                        Some(self.collect().await)
+                    } else {
+                        None
+                    };
+                    let Some(result) = result.filter(|result| !result.as_ref().is_err_and(is_gone)) else {
+                        self.exit_event().await?;
+                        self.fire_on_exit().await?;
+                        if self.follow_restarts && self.selector.is_some() {
+                            log::info!("{} exited, waiting for it to restart", self.target);
+                            waiting = true;
+                            continue;
                         }
-                        Ok(data) => {
-                            log::info!("recording {}...", self.process.pid());
-                            self.store_stream.write(&data).await.expect("an error occurred while writing to the store stream");
+                        break ExitReason::TargetExited
+                    };
+
+                    self.collections += 1;
+                    match result {
+                        // 跳过这次采样
+                        Err(err) => self.record_error("collection", err).await?,
+                        Ok((data, errors)) => {
+                            for (collector, err) in errors {
+                                self.record_error(collector, err).await?;
+                            }
+                            self.check_lifecycle().await?;
+                            log::info!("recording {}...", self.target);
+                            if let Some(adaptive) = self.adaptive {
+                                if last.as_ref().is_some_and(|last| adaptive.is_burst(last, &data)) {
+                                    if burst.replace(Instant::now()).is_none() {
+                                        log::info!("{} changed sharply, sampling every {:?}", self.target, adaptive.fast);
+                                        interval = tokio::time::interval_at((Instant::now() + adaptive.fast).into(), adaptive.fast);
+                                    }
+                                } else if burst.is_some_and(|time| time.elapsed() >= frequency) {
+                                    burst = None;
+                                    log::info!("{} is stable, sampling every {:?}", self.target, frequency);
+                                    interval = tokio::time::interval_at((Instant::now() + frequency).into(), frequency);
+                                }
+                            }
+                            if let Some(reason) = self.trigger.as_mut().and_then(|trigger| trigger.check(&data)) {
+                                self.fire(reason).await?;
+                            }
+                            self.persist(Record::Data(data.clone())).await?;
+
+                            samples += 1;
+                            if self.stop.reached_samples(samples) {
+                                break ExitReason::Finished(format!("reached {} samples", samples))
+                            }
+                            // 第一次采样没有上一次的cpu读数
+                            let cpu_usage = last.as_ref().map(|_| data.cpu_usage.0);
+                            if self.stop.is_idle(&mut idle_since, cpu_usage, Instant::now()) {
+                                break ExitReason::Finished(format!("idle for {:?}", self.stop.idle_for.unwrap_or_default()))
+                            }
+                            last = Some(data);
                         }
                     }
                 }
             }
+        };
+
+        Ok(reason)
+    }
+
+    // 在Supervisor中的id, 用来单独取消这个记录
+    pub fn id(&self) -> u64 {
+        self.shutdown_guard.id()
+    }
+
+    // 同时比较pid和开始时间, pid被其他进程重用时也不再运行
+    async fn is_running(&self) -> bool {
+        match &self.target {
+            Target::Process { process, .. } => process.is_running().await.unwrap_or(false),
+            // cgroup被删除时目录不存在
+            #[cfg(target_os = "linux")]
+            Target::Cgroup { dir, .. } => tokio::fs::metadata(dir).await.is_ok(),
         }
+    }
 
-        log::info!("stopping recording {}", self.process.pid());
-        self.store_stream
-            .flush()
-            .await
-            .expect("an error occurred while flushing to the store stream");
+    // 只有内存, cpu和io失败时整个采样失败
+    async fn collect(&mut self) -> anyhow::Result<(Data, Errors)> {
+        let mut errors = Vec::new();
+        let mut data = match &mut self.target {
+            Target::Process {
+                process,
+                last_cpu_usage,
+                last_threads,
+            } => {
+                Monitor::collect_process(
+                    process,
+                    last_cpu_usage,
+                    last_threads,
+                    &self.collectors,
+                    &mut errors,
+                )
+                .await?
+            }
+            #[cfg(target_os = "linux")]
+            Target::Cgroup {
+                root,
+                dir,
+                last_usage,
+            } => Monitor::collect_cgroup(root, dir, last_usage, &self.collectors).await?,
+        };
+
+        // 僵尸进程的部分采集项仍然可以读取
+        if let Some(i) = errors.iter().position(|(_, err)| is_gone(err)) {
+            return Err(errors.swap_remove(i).1);
+        }
+
+        if let Some(host) = &mut self.host {
+            data.host = optional(&mut errors, "host", host.collect().await);
+        }
+
+        data.custom = self.custom.sample();
+
+        // limits只在变化时记录
+        if data.limits.is_some() && data.limits == self.last_limits {
+            data.limits = None;
+        } else {
+            self.last_limits = data.limits.clone();
+        }
+
+        Ok((data, errors))
     }
 
-    async fn collect(&mut self) -> anyhow::Result<Data> {
-        let mem = self.process.memory().await?;
-        let cpu_time = self.process.cpu_time().await?;
-        let now_cpu_usage = self.process.cpu_usage().await?;
-        let cpu_usage = self
-            .last_cpu_usage
+    async fn collect_process(
+        process: &Process,
+        last_cpu_usage: &mut Option<HeimCpuUsage>,
+        last_threads: &mut Option<(HashMap<Pid, (u64, u32)>, Instant)>,
+        collectors: &Collectors,
+        errors: &mut Errors,
+    ) -> anyhow::Result<Data> {
+        let mem = process.memory().await?;
+        let cpu_time = process.cpu_time().await?;
+        let now_cpu_usage = process.cpu_usage().await?;
+        let cpu_usage = last_cpu_usage
             .take()
             .map(|last_cpu_usage| {
                 (now_cpu_usage.clone() - last_cpu_usage).get::<heim::units::ratio::percent>()
             })
             .unwrap_or(0f32);
-        self.last_cpu_usage = Some(now_cpu_usage);
+        *last_cpu_usage = Some(now_cpu_usage);
 
         cfg_if::cfg_if! {
             if #[cfg(target_os = "linux")] {
                 use heim::process::os::linux::IoCountersExt;
 
-                let io = self.process.io_counters().await?;
+                let io = process.io_counters().await?;
                 let io = Io {
-                    bytes_written: io.bytes_written().get::<heim::units::information::byte>(),
-                    bytes_read: io.bytes_read().get::<heim::units::information::byte>(),
-                    disk_written: Some(io.chars_written().get::<heim::units::information::byte>()),
-                    disk_read: Some(io.chars_read().get::<heim::units::information::byte>()),
+                    storage_written: io.bytes_written().get::<heim::units::information::byte>(),
+                    storage_read: io.bytes_read().get::<heim::units::information::byte>(),
+                    chars_written: Some(io.chars_written().get::<heim::units::information::byte>()),
+                    chars_read: Some(io.chars_read().get::<heim::units::information::byte>()),
                     syscall_written: Some(io.write_syscalls()),
                     syscall_read: Some(io.read_syscalls()),
+                    cancelled_write_bytes: Some(io.cancelled_write_bytes().get::<heim::units::information::byte>()),
                 };
             } else {
-                let io = self.process.io_counters().await?;
+                let io = process.io_counters().await?;
             }
         }
 
@@ -158,25 +989,439 @@ impl Monitor {
                 use heim::process::os::linux::ProcessExt;
                 use crate::data::NetIo;
 
-                let mut net_io_stream = self.process.net_io_counters().await?;
                 let mut net_io = HashMap::default();
-
-                while let Some(Ok(io)) = net_io_stream.next().await {
-                    net_io.insert(io.interface().to_owned(), NetIo::from(io));
+                if let Some(mut net_io_stream) =
+                    optional(errors, "net_io", process.net_io_counters().await)
+                {
+                    while let Some(Ok(io)) = net_io_stream.next().await {
+                        net_io.insert(io.interface().to_owned(), NetIo::from(io));
+                    }
                 }
             } else {
                 let net_io = HashMap::default();
             }
         }
 
+        cfg_if::cfg_if! {
+            if #[cfg(target_os = "linux")] {
+                let sched = optional(errors, "sched", crate::procfs::sched(process.pid()).await);
+                let fd = if collectors.fd {
+                    optional(errors, "fd", crate::procfs::fd(process.pid()).await)
+                } else {
+                    None
+                };
+            } else {
+                let sched = None;
+                let fd = None;
+            }
+        }
+
+        cfg_if::cfg_if! {
+            if #[cfg(target_os = "linux")] {
+                let mut limits =
+                    optional(errors, "limits", crate::procfs::limits(process.pid()).await);
+                // 不在cgroup v2中时没有cgroup的限制和压力
+                let root = crate::cgroup::mount_point().await;
+                let cgroup = crate::cgroup::process_cgroup(root, process.pid()).await.ok();
+                if let (Some(limits), Some(cgroup)) = (&mut limits, &cgroup) {
+                    limits.memory_max = crate::cgroup::memory_max(cgroup, root).await;
+                    limits.cpu_max = crate::cgroup::cpu_max(cgroup, root).await;
+                }
+
+                let psi = if collectors.psi {
+                    Some(Psi {
+                        system: crate::procfs::system_pressure().await,
+                        cgroup: match &cgroup {
+                            Some(cgroup) => crate::cgroup::pressure(cgroup).await,
+                            None => HashMap::default(),
+                        },
+                    })
+                } else {
+                    None
+                };
+
+                let cores = if collectors.cores {
+                    let cores = async {
+                        let (affinity, last_cpu) = crate::procfs::affinity(process.pid()).await?;
+                        let threads = crate::procfs::threads(process.pid()).await?;
+                        let now = Instant::now();
+
+                        let mut usage = BTreeMap::new();
+                        if let Some((last, time)) = last_threads.replace((threads.clone(), now)) {
+                            let elapsed = now.duration_since(time).as_micros() as f32;
+                            for (tid, (cpu_time, cpu)) in threads {
+                                // 新创建的线程全部的cpu时间都在这段时间内
+                                let last_cpu_time = last.get(&tid).map(|(t, _)| *t).unwrap_or(0);
+                                *usage.entry(cpu).or_insert(0f32) +=
+                                    cpu_time.saturating_sub(last_cpu_time) as f32 / elapsed * 100.0;
+                            }
+                        }
+
+                        anyhow::Ok(CpuCores {
+                            affinity,
+                            last_cpu,
+                            usage,
+                        })
+                    };
+                    optional(errors, "cores", cores.await)
+                } else {
+                    None
+                };
+
+                let files = if collectors.files {
+                    optional(errors, "files", crate::procfs::file_positions(process.pid()).await)
+                } else {
+                    None
+                };
+                let io_attribution = if let Some(files) = files {
+                    Some(IoAttribution {
+                        files,
+                        devices: match &cgroup {
+                            Some(cgroup) => {
+                                crate::cgroup::devices(&crate::cgroup::io_stat(cgroup).await).await
+                            }
+                            None => HashMap::default(),
+                        },
+                    })
+                } else {
+                    None
+                };
+            } else {
+                let limits = None;
+                let psi = None;
+                let cores = None;
+                let io_attribution = None;
+                let _ = (last_threads, errors);
+            }
+        }
+
         let data = Data {
             memory: Memory::from(mem),
             cpu_time: CpuTime::from(cpu_time),
             cpu_usage: CpuUsage(cpu_usage),
             io: Io::from(io),
             net_io,
+            time: Some(chrono::Utc::now().timestamp_millis() as u64),
+            sched,
+            fd,
+            limits,
+            cgroup: None,
+            psi,
+            host: None,
+            cores,
+            io_attribution,
+            custom: None,
         };
 
         Ok(data)
     }
+
+    #[cfg(target_os = "linux")]
+    async fn collect_cgroup(
+        root: &Path,
+        dir: &Path,
+        last_usage: &mut Option<(u64, Instant)>,
+        collectors: &Collectors,
+    ) -> anyhow::Result<Data> {
+        let stat = crate::cgroup::stat(dir).await?;
+        let psi = if collectors.psi {
+            Some(Psi {
+                system: crate::procfs::system_pressure().await,
+                cgroup: stat.pressure.clone(),
+            })
+        } else {
+            None
+        };
+
+        // cgroup中没有单独的进程, 只有块设备
+        let io_attribution = if collectors.files {
+            Some(IoAttribution {
+                files: HashMap::default(),
+                devices: crate::cgroup::devices(&stat.io_stat).await,
+            })
+        } else {
+            None
+        };
+
+        let cpu_stat = |key: &str| stat.cpu_stat.get(key).copied().unwrap_or(0);
+        let now_usage = (cpu_stat("usage_usec"), Instant::now());
+        let cpu_usage = last_usage
+            .replace(now_usage)
+            .map(|(usage, time)| {
+                let elapsed = now_usage.1.duration_since(time).as_micros() as f32;
+                now_usage.0.saturating_sub(usage) as f32 / elapsed * 100.0
+            })
+            .unwrap_or(0f32);
+
+        let io_stat = |key: &str| stat.io_stat.values().filter_map(|io| io.get(key)).sum();
+        let memory_stat = |key: &str| stat.memory_stat.get(key).copied();
+
+        Ok(Data {
+            memory: Memory {
+                rss: stat.memory_current,
+                vms: 0,
+                shared: memory_stat("shmem"),
+                text: None,
+                data: memory_stat("anon"),
+            },
+            cpu_time: CpuTime {
+                user: cpu_stat("user_usec") as f64,
+                system: cpu_stat("system_usec") as f64,
+            },
+            cpu_usage: CpuUsage(cpu_usage),
+            io: Io {
+                storage_written: io_stat("wbytes"),
+                storage_read: io_stat("rbytes"),
+                chars_written: None,
+                chars_read: None,
+                syscall_written: None,
+                syscall_read: None,
+                cancelled_write_bytes: None,
+            },
+            net_io: HashMap::default(),
+            time: Some(chrono::Utc::now().timestamp_millis() as u64),
+            sched: None,
+            fd: None,
+            limits: Some(Limits {
+                address_space: None,
+                data: None,
+                nofile: None,
+                nproc: None,
+                memory_max: crate::cgroup::memory_max(dir, root).await,
+                cpu_max: crate::cgroup::cpu_max(dir, root).await,
+            }),
+            cgroup: Some(stat),
+            psi,
+            host: None,
+            cores: None,
+            io_attribution,
+            custom: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::supervisor::Supervisor;
+
+    async fn current_process(out_dir: &Path) -> Monitor {
+        let process = heim::process::current().await.unwrap();
+        let guard = Supervisor::new().start().unwrap();
+        Monitor::new(process, out_dir, Collectors::default(), guard)
+            .await
+            .unwrap()
+    }
+
+    // 目录中的全部中间文件
+    async fn stores(dir: &Path) -> Vec<StoreStream> {
+        let mut stores = Vec::new();
+        for entry in std::fs::read_dir(dir).unwrap() {
+            stores.push(StoreStream::open(entry.unwrap().path()).await.unwrap());
+        }
+        stores
+    }
+
+    fn idle_stop() -> Stop {
+        Stop {
+            samples: None,
+            idle_for: Some(Duration::from_secs(10)),
+            idle_cpu: 1.0,
+        }
+    }
+
+    #[test]
+    fn stop_after_samples() {
+        let stop = Stop {
+            samples: Some(3),
+            ..Stop::default()
+        };
+        assert!(!stop.reached_samples(2));
+        assert!(stop.reached_samples(3));
+        assert!(!Stop::default().reached_samples(u64::MAX));
+    }
+
+    #[test]
+    fn stop_when_idle() {
+        let stop = idle_stop();
+        let start = Instant::now();
+        let mut idle_since = None;
+
+        assert!(!stop.is_idle(&mut idle_since, Some(0.5), start));
+        assert!(!stop.is_idle(&mut idle_since, Some(0.5), start + Duration::from_secs(5)));
+        assert!(stop.is_idle(&mut idle_since, Some(0.5), start + Duration::from_secs(10)));
+
+        // 使用率升高时重新计时
+        let mut idle_since = None;
+        assert!(!stop.is_idle(&mut idle_since, Some(0.5), start));
+        assert!(!stop.is_idle(&mut idle_since, Some(50.0), start + Duration::from_secs(5)));
+        assert!(!stop.is_idle(&mut idle_since, Some(0.5), start + Duration::from_secs(10)));
+        assert!(stop.is_idle(&mut idle_since, Some(0.5), start + Duration::from_secs(20)));
+    }
+
+    #[test]
+    fn idle_skips_samples_without_previous_cpu() {
+        let stop = idle_stop();
+        let start = Instant::now();
+        let mut idle_since = None;
+
+        // 第一次采样的使用率总是0, 不开始计时
+        assert!(!stop.is_idle(&mut idle_since, None, start));
+        assert!(idle_since.is_none());
+        assert!(!stop.is_idle(&mut idle_since, Some(0.5), start + Duration::from_secs(10)));
+        assert!(stop.is_idle(&mut idle_since, Some(0.5), start + Duration::from_secs(20)));
+
+        // 没有设置`idle_for`
+        let mut idle_since = None;
+        assert!(!Stop::default().is_idle(&mut idle_since, Some(0.0), start));
+    }
+
+    #[tokio::test]
+    async fn stores_in_same_second() {
+        let dir = tempfile::tempdir().unwrap();
+        for _ in 0..3 {
+            let mut store = Monitor::create_store("1234", dir.path(), None)
+                .await
+                .unwrap();
+            store.flush().await.unwrap();
+        }
+        // 同一秒内创建的文件加上序号
+        let names = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(names.len(), 3);
+        assert!(names.iter().all(|name| name.starts_with("1234-")));
+    }
+
+    #[tokio::test]
+    async fn rotate_starts_new_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut monitor = current_process(dir.path()).await;
+        monitor.last_limits = Some(Limits {
+            address_space: None,
+            data: None,
+            nofile: Some(1024),
+            nproc: None,
+            memory_max: None,
+            cpu_max: None,
+        });
+
+        monitor.rotate().await.unwrap();
+        monitor.store_stream.flush().await.unwrap();
+        // 新的文件从第一次采样开始重新记录limits
+        assert!(monitor.last_limits.is_none());
+
+        let stores = stores(dir.path()).await;
+        assert_eq!(stores.len(), 2);
+        for store in &stores {
+            let header = store.header().unwrap();
+            assert_eq!(header.version, VERSION);
+            assert_eq!(header.cores, num_cpus::get() as u32);
+            // 每个文件都带有进程的信息
+            let process = header.process.as_ref().unwrap();
+            assert_eq!(process.pid, std::process::id());
+            assert!(!process.cmdline.is_empty());
+            assert!(process.start_time.is_some());
+        }
+    }
+
+    #[test]
+    fn optional_collects_errors() {
+        let mut errors = Errors::new();
+        assert_eq!(
+            optional(&mut errors, "fd", Ok::<_, anyhow::Error>(1)),
+            Some(1)
+        );
+        assert_eq!(
+            optional(&mut errors, "psi", Err::<u32, _>(anyhow::anyhow!("no psi"))),
+            None
+        );
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, "psi");
+    }
+
+    #[test]
+    fn gone_processes() {
+        assert!(is_gone(&ProcessError::NoSuchProcess(1).into()));
+        assert!(is_gone(&ProcessError::ZombieProcess(1).into()));
+        assert!(is_gone(
+            &anyhow::Error::from(ProcessError::ZombieProcess(1)).context("reading stat")
+        ));
+        // 权限不足时进程还在
+        assert!(!is_gone(&ProcessError::AccessDenied(1).into()));
+        assert!(!is_gone(&anyhow::anyhow!("no such file")));
+    }
+
+    #[tokio::test]
+    async fn errors_are_recorded_and_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut monitor = current_process(dir.path()).await;
+        monitor.collections = 3;
+        for _ in 0..3 {
+            monitor
+                .record_error("fd", anyhow::anyhow!("permission denied"))
+                .await
+                .unwrap();
+        }
+        monitor
+            .record_error("psi", anyhow::anyhow!("not supported"))
+            .await
+            .unwrap();
+        monitor.store_stream.flush().await.unwrap();
+
+        assert_eq!(
+            monitor.error_report(),
+            [
+                (
+                    log::Level::Error,
+                    "fd failed in every sample: permission denied".to_owned()
+                ),
+                (
+                    log::Level::Warn,
+                    "psi failed in 1/3 samples, last error: not supported".to_owned()
+                ),
+            ]
+        );
+
+        // 每个错误都写入中间文件
+        let mut store = stores(dir.path()).await.remove(0);
+        let mut collectors = Vec::new();
+        while let Some(record) = store.read().await.unwrap() {
+            if let Record::Error(err) = record {
+                collectors.push(err.collector);
+            }
+        }
+        assert_eq!(collectors, ["fd", "fd", "fd", "psi"]);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn find_by_cmdline() {
+        // 不会出现在其他进程命令行中的参数
+        let arg = format!("30.{}", std::process::id());
+        let mut child = std::process::Command::new("sleep")
+            .arg(&arg)
+            .spawn()
+            .unwrap();
+
+        let selector = Selector::Cmdline(format!("sleep {}", arg));
+        // 刚fork的子进程可能还没有exec
+        let mut found = None;
+        for _ in 0..100 {
+            found = selector.find(None).await.unwrap().map(|p| p.pid() as u32);
+            if found.is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        // 排除找到的进程后没有其他匹配的进程
+        let process = heim::process::get(child.id() as Pid).await.unwrap();
+        let excluded = selector.find(Some(&process)).await.unwrap();
+
+        child.kill().unwrap();
+        child.wait().unwrap();
+        assert_eq!(found, Some(child.id()));
+        assert!(excluded.is_none());
+    }
 }
@@ -0,0 +1,283 @@
+use std::str::FromStr;
+
+use serde_json::Value;
+
+use crate::data::{Data, Event, EventKind};
+
+// `render --plot`绘制的指标, 逗号分隔
+#[derive(Debug, Clone)]
+pub struct Plot(pub Vec<Metric>);
+
+impl FromStr for Plot {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let metrics = s
+            .split(',')
+            .map(str::trim)
+            .filter(|expr| !expr.is_empty())
+            .map(str::parse)
+            .collect::<Result<Vec<_>, _>>()?;
+        if metrics.is_empty() {
+            return Err("no metrics to plot".to_owned());
+        }
+        Ok(Plot(metrics))
+    }
+}
+
+// 一个指标, 如`memory.rss`, `rate(io.storage_read)`.
+// 路径是result.json中的字段名, map的键和数组的下标也用`.`分隔
+#[derive(Debug, Clone)]
+pub struct Metric {
+    // 原样作为图例
+    pub expr: String,
+    pub path: String,
+    pub transform: Transform,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transform {
+    None,
+    // 每秒的变化量, 需要采样时间
+    Rate,
+    // 与上一个采样的差
+    Delta,
+}
+
+impl FromStr for Metric {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (transform, path) = match s.split_once('(') {
+            Some((name, rest)) => {
+                let path = rest
+                    .strip_suffix(')')
+                    .ok_or_else(|| format!("invalid metric {}, missing )", s))?;
+                let transform = match name.trim() {
+                    "rate" => Transform::Rate,
+                    "delta" => Transform::Delta,
+                    name => {
+                        return Err(format!(
+                            "unknown transform {}, expected rate or delta",
+                            name
+                        ))
+                    }
+                };
+                (transform, path.trim())
+            }
+            None => (Transform::None, s),
+        };
+        if path.is_empty() || path.contains(['(', ')']) {
+            return Err(format!("invalid metric {}", s));
+        }
+        Ok(Metric {
+            expr: s.to_owned(),
+            path: path.to_owned(),
+            transform,
+        })
+    }
+}
+
+impl Metric {
+    // 每个采样的值, `json`是每个采样序列化后的值. 没有这个指标的采样跳过.
+    // `restarts`是重启后第一个采样的位置, 计数器从头开始, 不和上一个采样比较
+    pub fn series(&self, data: &[Data], json: &[Value], restarts: &[usize]) -> Vec<(usize, f64)> {
+        let values = json
+            .iter()
+            .map(|json| lookup(json, &self.path))
+            .collect::<Vec<_>>();
+        match self.transform {
+            Transform::None => values
+                .iter()
+                .enumerate()
+                .filter_map(|(x, value)| Some((x, (*value)?)))
+                .collect(),
+            // 第一个采样没有上一次采样, 跳过
+            Transform::Rate | Transform::Delta => (1..values.len())
+                .filter(|x| !restarts.contains(x))
+                .filter_map(|x| {
+                    let change = values[x]? - values[x - 1]?;
+                    match self.transform {
+                        Transform::Rate => {
+                            let secs = data[x].elapsed_since(&data[x - 1])?.as_secs_f64();
+                            (secs > 0.0).then(|| (x, change / secs))
+                        }
+                        _ => Some((x, change)),
+                    }
+                })
+                .collect(),
+        }
+    }
+}
+
+// `--follow-restarts`开始记录新进程之后第一个采样的位置
+pub fn restarts(events: &[(usize, Event)]) -> Vec<usize> {
+    events
+        .iter()
+        .filter(|(_, event)| matches!(event.kind, EventKind::Start(_)))
+        .map(|(x, _)| *x)
+        .collect()
+}
+
+// 按`.`分隔的路径查找数值. map的键可能包含`.`(如自定义指标), 优先匹配最长的键
+fn lookup(value: &Value, path: &str) -> Option<f64> {
+    if path.is_empty() {
+        return value.as_f64();
+    }
+    match value {
+        Value::Object(map) => path
+            .match_indices('.')
+            .map(|(i, _)| i)
+            .chain([path.len()])
+            .rev()
+            .find_map(|i| {
+                let rest = path.get(i + 1..).unwrap_or_default();
+                lookup(map.get(&path[..i])?, rest)
+            }),
+        Value::Array(array) => {
+            let (index, rest) = path.split_once('.').unwrap_or((path, ""));
+            lookup(array.get(index.parse::<usize>().ok()?)?, rest)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::data::tests::sample;
+    use crate::data::ProcessInfo;
+
+    fn metric(s: &str) -> Metric {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parse_metrics() {
+        let plot = " memory.rss, rate(io.storage_read) ,,delta( sched.minor_faults )"
+            .parse::<Plot>()
+            .unwrap();
+        let parsed = plot
+            .0
+            .iter()
+            .map(|m| (m.expr.as_str(), m.path.as_str(), m.transform))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            parsed,
+            [
+                ("memory.rss", "memory.rss", Transform::None),
+                ("rate(io.storage_read)", "io.storage_read", Transform::Rate),
+                (
+                    "delta( sched.minor_faults )",
+                    "sched.minor_faults",
+                    Transform::Delta
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_metrics() {
+        assert!(" , ,".parse::<Plot>().is_err());
+        assert!("".parse::<Plot>().is_err());
+        assert!("avg(memory.rss)".parse::<Metric>().is_err());
+        assert!("rate(memory.rss".parse::<Metric>().is_err());
+        assert!("memory.rss)".parse::<Metric>().is_err());
+        assert!("rate(rate(memory.rss))".parse::<Metric>().is_err());
+        assert!("rate()".parse::<Metric>().is_err());
+    }
+
+    #[test]
+    fn lookup_paths() {
+        let value = json!({
+            "memory": {"rss": 10},
+            "custom": {"queue": {"depth": 1}, "queue.depth": 2},
+            "host": {"cpu_usage_per_core": [5.0, 6.5]},
+        });
+        assert_eq!(lookup(&value, "memory.rss"), Some(10.0));
+        // 优先匹配最长的键
+        assert_eq!(lookup(&value, "custom.queue.depth"), Some(2.0));
+        assert_eq!(lookup(&value, "host.cpu_usage_per_core.1"), Some(6.5));
+        assert_eq!(lookup(&value, "host.cpu_usage_per_core.2"), None);
+        assert_eq!(lookup(&value, "host.cpu_usage_per_core.x"), None);
+        assert_eq!(lookup(&value, "memory"), None);
+        assert_eq!(lookup(&value, "memory.vms"), None);
+    }
+
+    #[test]
+    fn transforms() {
+        let data = [
+            sample(Some(0)),
+            sample(Some(500)),
+            sample(Some(1500)),
+            sample(Some(2500)),
+        ];
+        let json = [10, 20, 40, 30]
+            .map(|rss| json!({"memory": {"rss": rss}}))
+            .to_vec();
+        assert_eq!(
+            metric("memory.rss").series(&data, &json, &[]),
+            [(0, 10.0), (1, 20.0), (2, 40.0), (3, 30.0)]
+        );
+        assert_eq!(
+            metric("delta(memory.rss)").series(&data, &json, &[]),
+            [(1, 10.0), (2, 20.0), (3, -10.0)]
+        );
+        assert_eq!(
+            metric("rate(memory.rss)").series(&data, &json, &[]),
+            [(1, 20.0), (2, 20.0), (3, -10.0)]
+        );
+    }
+
+    #[test]
+    fn rate_needs_time() {
+        let data = [sample(None), sample(None)];
+        let json = [
+            json!({"io": {"storage_read": 0}}),
+            json!({"io": {"storage_read": 10}}),
+        ];
+        assert!(metric("rate(io.storage_read)")
+            .series(&data, &json, &[])
+            .is_empty());
+        assert_eq!(
+            metric("delta(io.storage_read)").series(&data, &json, &[]),
+            [(1, 10.0)]
+        );
+    }
+
+    #[test]
+    fn restarts_break_changes() {
+        let data = [sample(Some(0)), sample(Some(1000)), sample(Some(2000))];
+        let json = [100, 200, 5]
+            .map(|read| json!({"io": {"storage_read": read}}))
+            .to_vec();
+        let events = [
+            (
+                1,
+                Event {
+                    time: 500,
+                    kind: EventKind::Priority { nice: 5 },
+                },
+            ),
+            (
+                2,
+                Event {
+                    time: 1500,
+                    kind: EventKind::Start(ProcessInfo {
+                        pid: 2,
+                        ..Default::default()
+                    }),
+                },
+            ),
+        ];
+        let restarts = restarts(&events);
+        assert_eq!(restarts, [2]);
+        // 重启后计数器从头开始, 不画成负的尖峰
+        assert_eq!(
+            metric("rate(io.storage_read)").series(&data, &json, &restarts),
+            [(1, 100.0)]
+        );
+    }
+}
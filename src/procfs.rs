@@ -0,0 +1,426 @@
+// heim没有提供的/proc/<pid>下的信息, 直接从procfs读取
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use heim::process::Pid;
+
+use crate::data::{Fd, FilePosition, Limits, Pressure, Sched};
+
+fn proc_path(pid: Pid, name: &str) -> PathBuf {
+    PathBuf::from("/proc").join(pid.to_string()).join(name)
+}
+
+// /proc/<pid>/status中`key:\tvalue`格式的字段
+fn status_field<'a>(status: &'a str, key: &str) -> Option<&'a str> {
+    status.lines().find_map(|line| {
+        line.strip_prefix(key)
+            .and_then(|rest| rest.strip_prefix(':'))
+            .map(str::trim)
+    })
+}
+
+// /proc/<pid>/stat中进程名(comm)之后的字段, 第一个元素是第3个字段(state).
+// 进程名可能包含空格和括号, 所以从最后一个`)`开始分割
+fn stat_fields(stat: &str) -> anyhow::Result<Vec<&str>> {
+    let start = stat
+        .rfind(')')
+        .ok_or_else(|| anyhow::anyhow!("malformed stat: {}", stat))?;
+    Ok(stat[start + 1..].split_whitespace().collect())
+}
+
+fn parse_u64(s: Option<&str>, name: &str) -> anyhow::Result<u64> {
+    s.ok_or_else(|| anyhow::anyhow!("missing field {}", name))?
+        .parse()
+        .map_err(|err| anyhow::anyhow!("invalid field {}: {}", name, err))
+}
+
+pub async fn sched(pid: Pid) -> anyhow::Result<Sched> {
+    let status = tokio::fs::read_to_string(proc_path(pid, "status")).await?;
+    let stat = tokio::fs::read_to_string(proc_path(pid, "stat")).await?;
+    // 内核没有开启CONFIG_SCHED_INFO时不存在
+    let schedstat = tokio::fs::read_to_string(proc_path(pid, "schedstat"))
+        .await
+        .ok();
+    parse_sched(&status, &stat, schedstat.as_deref())
+}
+
+fn parse_sched(status: &str, stat: &str, schedstat: Option<&str>) -> anyhow::Result<Sched> {
+    let stat = stat_fields(stat)?;
+    let schedstat = schedstat.map(|s| s.split_whitespace().collect::<Vec<_>>());
+
+    Ok(Sched {
+        voluntary_ctxt_switches: parse_u64(
+            status_field(status, "voluntary_ctxt_switches"),
+            "voluntary_ctxt_switches",
+        )?,
+        nonvoluntary_ctxt_switches: parse_u64(
+            status_field(status, "nonvoluntary_ctxt_switches"),
+            "nonvoluntary_ctxt_switches",
+        )?,
+        // stat的第10和第12个字段
+        minor_faults: parse_u64(stat.get(7).copied(), "minflt")?,
+        major_faults: parse_u64(stat.get(9).copied(), "majflt")?,
+        run_delay: schedstat
+            .as_ref()
+            .map(|s| parse_u64(s.get(1).copied(), "run_delay"))
+            .transpose()?,
+        timeslices: schedstat
+            .as_ref()
+            .map(|s| parse_u64(s.get(2).copied(), "timeslices"))
+            .transpose()?,
+    })
+}
+
+// /proc/<pid>/limits中的软限制, `unlimited`为None
+fn parse_limit(limits: &str, name: &str) -> Option<u64> {
+    let line = limits.lines().find(|line| line.starts_with(name))?;
+    line[name.len()..].split_whitespace().next()?.parse().ok()
+}
+
+// 只包含rlimit, cgroup的限制见`crate::cgroup`
+pub async fn limits(pid: Pid) -> anyhow::Result<Limits> {
+    let limits = tokio::fs::read_to_string(proc_path(pid, "limits")).await?;
+    Ok(Limits {
+        address_space: parse_limit(&limits, "Max address space"),
+        data: parse_limit(&limits, "Max data size"),
+        nofile: parse_limit(&limits, "Max open files"),
+        nproc: parse_limit(&limits, "Max processes"),
+        memory_max: None,
+        cpu_max: None,
+    })
+}
+
+pub async fn fd(pid: Pid) -> anyhow::Result<Fd> {
+    let mut fd = Fd::default();
+    let mut socket_inodes = HashSet::new();
+
+    let mut dir = tokio::fs::read_dir(proc_path(pid, "fd")).await?;
+    while let Some(entry) = dir.next_entry().await? {
+        // 在读取目录和读取链接之间fd可能已经被关闭
+        let target = match tokio::fs::read_link(entry.path()).await {
+            Ok(target) => target,
+            Err(_) => continue,
+        };
+        let target = target.to_string_lossy();
+
+        fd.total += 1;
+        if let Some(inode) = target
+            .strip_prefix("socket:[")
+            .and_then(|s| s.strip_suffix(']'))
+        {
+            fd.socket += 1;
+            socket_inodes.insert(inode.to_owned());
+        } else if target.starts_with("pipe:") {
+            fd.pipe += 1;
+        } else if target == "anon_inode:[eventfd]" {
+            fd.eventfd += 1;
+        } else if target.starts_with("anon_inode:") {
+            fd.anon_inode += 1;
+        } else {
+            fd.file += 1;
+        }
+    }
+
+    for (table, tcp) in [
+        ("net/tcp", true),
+        ("net/tcp6", true),
+        ("net/udp", false),
+        ("net/udp6", false),
+    ] {
+        // 没有开启ipv6时不存在
+        if let Ok(content) = tokio::fs::read_to_string(proc_path(pid, table)).await {
+            let states = if tcp { &mut fd.tcp } else { &mut fd.udp };
+            count_socket_states(&content, &socket_inodes, states);
+        }
+    }
+
+    Ok(fd)
+}
+
+// 打开的普通文件和/proc/<pid>/fdinfo中的读写位置, 同一个文件的多个fd相加
+pub async fn file_positions(pid: Pid) -> anyhow::Result<HashMap<String, FilePosition>> {
+    let mut files = HashMap::<String, FilePosition>::new();
+
+    let mut dir = tokio::fs::read_dir(proc_path(pid, "fd")).await?;
+    while let Some(entry) = dir.next_entry().await? {
+        // 在读取目录和读取fdinfo之间fd可能已经被关闭
+        let target = match tokio::fs::read_link(entry.path()).await {
+            Ok(target) => target,
+            Err(_) => continue,
+        };
+        // socket, pipe等不是路径. 设备, /proc和/sys中的文件, 已经删除的文件也不计入
+        if !is_regular_file(&target)
+            || !tokio::fs::metadata(entry.path())
+                .await
+                .is_ok_and(|m| m.is_file())
+        {
+            continue;
+        }
+        let fdinfo =
+            match tokio::fs::read_to_string(proc_path(pid, "fdinfo").join(entry.file_name())).await
+            {
+                Ok(fdinfo) => fdinfo,
+                Err(_) => continue,
+            };
+
+        let pos = parse_u64(status_field(&fdinfo, "pos"), "pos")?;
+        // flags是八进制
+        let flags = status_field(&fdinfo, "flags")
+            .and_then(|flags| u32::from_str_radix(flags, 8).ok())
+            .unwrap_or(0);
+        let access = flags & libc::O_ACCMODE as u32;
+
+        let file = files
+            .entry(target.to_string_lossy().into_owned())
+            .or_default();
+        file.pos += pos;
+        file.read |= access != libc::O_WRONLY as u32;
+        file.write |= access != libc::O_RDONLY as u32;
+    }
+
+    Ok(files)
+}
+
+// fd指向的路径是否可能是普通文件. /proc和/sys中的文件的类型也是普通文件
+fn is_regular_file(target: &Path) -> bool {
+    target.is_absolute()
+        && !target.starts_with("/proc")
+        && !target.starts_with("/sys")
+        && !target.to_string_lossy().ends_with(" (deleted)")
+}
+
+// 统计/proc/<pid>/net/{tcp,udp}中属于该进程的socket的状态
+fn count_socket_states(table: &str, inodes: &HashSet<String>, states: &mut HashMap<String, u64>) {
+    for line in table.lines().skip(1) {
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+        let (state, inode) = match (fields.get(3), fields.get(9)) {
+            (Some(state), Some(inode)) => (state, inode),
+            _ => continue,
+        };
+        if !inodes.contains(*inode) {
+            continue;
+        }
+
+        let state = match u8::from_str_radix(state, 16).unwrap_or(0) {
+            0x01 => "ESTABLISHED",
+            0x02 => "SYN_SENT",
+            0x03 => "SYN_RECV",
+            0x04 => "FIN_WAIT1",
+            0x05 => "FIN_WAIT2",
+            0x06 => "TIME_WAIT",
+            0x07 => "CLOSE",
+            0x08 => "CLOSE_WAIT",
+            0x09 => "LAST_ACK",
+            0x0A => "LISTEN",
+            0x0B => "CLOSING",
+            _ => "UNKNOWN",
+        };
+        *states.entry(state.to_owned()).or_default() += 1;
+    }
+}
+
+// 系统全局的/proc/pressure/{cpu,memory,io}, 内核没有开启PSI时为空
+pub async fn system_pressure() -> HashMap<String, Pressure> {
+    let mut pressure = HashMap::new();
+    for resource in ["cpu", "memory", "io"] {
+        if let Some(p) = tokio::fs::read_to_string(PathBuf::from("/proc/pressure").join(resource))
+            .await
+            .ok()
+            .as_deref()
+            .and_then(crate::cgroup::parse_pressure)
+        {
+            pressure.insert(resource.to_owned(), p);
+        }
+    }
+    pressure
+}
+
+// Cpus_allowed_list, 例如`0-3,8`
+fn parse_cpu_list(list: &str) -> Vec<u32> {
+    list.split(',')
+        .filter_map(|range| match range.trim().split_once('-') {
+            Some((start, end)) => Some((start.parse().ok()?..=end.parse().ok()?).collect()),
+            None => range.trim().parse().ok().map(|cpu| vec![cpu]),
+        })
+        .flatten()
+        .collect()
+}
+
+// 允许运行的核和最后一次运行所在的核
+pub async fn affinity(pid: Pid) -> anyhow::Result<(Vec<u32>, u32)> {
+    let status = tokio::fs::read_to_string(proc_path(pid, "status")).await?;
+    let stat = tokio::fs::read_to_string(proc_path(pid, "stat")).await?;
+
+    let affinity = parse_cpu_list(
+        status_field(&status, "Cpus_allowed_list")
+            .ok_or_else(|| anyhow::anyhow!("missing field Cpus_allowed_list"))?,
+    );
+    // stat的第39个字段
+    let last_cpu = parse_u64(stat_fields(&stat)?.get(36).copied(), "processor")? as u32;
+    Ok((affinity, last_cpu))
+}
+
+// 每个线程的累计cpu时间(微秒)和最后一次运行所在的核
+pub async fn threads(pid: Pid) -> anyhow::Result<HashMap<Pid, (u64, u32)>> {
+    let ticks = clock_ticks();
+    let mut threads = HashMap::new();
+
+    let mut dir = tokio::fs::read_dir(proc_path(pid, "task")).await?;
+    while let Some(entry) = dir.next_entry().await? {
+        let tid = match entry.file_name().to_str().and_then(|tid| tid.parse().ok()) {
+            Some(tid) => tid,
+            None => continue,
+        };
+        // 线程可能已经退出
+        let stat = match tokio::fs::read_to_string(entry.path().join("stat")).await {
+            Ok(stat) => stat,
+            Err(_) => continue,
+        };
+        let stat = stat_fields(&stat)?;
+
+        // stat的第14, 15, 39个字段
+        let cpu_time =
+            parse_u64(stat.get(11).copied(), "utime")? + parse_u64(stat.get(12).copied(), "stime")?;
+        let processor = parse_u64(stat.get(36).copied(), "processor")? as u32;
+        threads.insert(tid, (cpu_time * 1_000_000 / ticks, processor));
+    }
+
+    Ok(threads)
+}
+
+// stat中的cpu时间以clock tick为单位
+fn clock_ticks() -> u64 {
+    match unsafe { libc::sysconf(libc::_SC_CLK_TCK) } {
+        ticks if ticks > 0 => ticks as u64,
+        _ => 100,
+    }
+}
+
+// 实际的用户和组id
+pub async fn owner(pid: Pid) -> anyhow::Result<(u32, u32)> {
+    parse_owner(&tokio::fs::read_to_string(proc_path(pid, "status")).await?)
+}
+
+fn parse_owner(status: &str) -> anyhow::Result<(u32, u32)> {
+    // `Uid:`和`Gid:`之后依次是real, effective, saved, filesystem
+    let real = |key| status_field(status, key).and_then(|ids| ids.split_whitespace().next());
+    Ok((
+        parse_u64(real("Uid"), "Uid")? as u32,
+        parse_u64(real("Gid"), "Gid")? as u32,
+    ))
+}
+
+// /etc/passwd或/etc/group中id对应的名字, 不查询NSS
+pub async fn account_name(file: &str, id: u32) -> Option<String> {
+    parse_account_name(&tokio::fs::read_to_string(file).await.ok()?, id)
+}
+
+fn parse_account_name(accounts: &str, id: u32) -> Option<String> {
+    accounts.lines().find_map(|line| {
+        let mut fields = line.split(':');
+        let name = fields.next()?;
+        (fields.nth(1)?.parse::<u32>().ok()? == id).then(|| name.to_owned())
+    })
+}
+
+// 僵尸进程的退出码或者结束进程的信号
+pub async fn exit_status(pid: Pid) -> anyhow::Result<(Option<i32>, Option<i32>)> {
+    parse_exit_status(&tokio::fs::read_to_string(proc_path(pid, "stat")).await?)
+}
+
+fn parse_exit_status(stat: &str) -> anyhow::Result<(Option<i32>, Option<i32>)> {
+    // stat的第52个字段, 与waitpid的status相同
+    let status = parse_u64(stat_fields(stat)?.get(49).copied(), "exit_code")? as i32;
+    if status & 0x7f == 0 {
+        Ok((Some((status >> 8) & 0xff), None))
+    } else {
+        Ok((None, Some(status & 0x7f)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STATUS: &str =
+        "Name:\tweird\nvoluntary_ctxt_switches:\t150\nnonvoluntary_ctxt_switches:\t7\n";
+    // 进程名包含空格和`)`
+    const STAT: &str =
+        "1234 (a b) c)) S 1 1234 1234 0 -1 4194560 311 0 2 0 5 3 0 0 20 0 1 0 100 1000 50\n";
+
+    #[test]
+    fn stat_fields_after_comm() {
+        let fields = stat_fields(STAT).unwrap();
+        assert_eq!(fields[0], "S");
+        assert_eq!(fields[1], "1");
+        assert!(stat_fields("1234 no comm").is_err());
+    }
+
+    #[test]
+    fn sched_fields() {
+        let sched = parse_sched(STATUS, STAT, Some("5000 2000000 40\n")).unwrap();
+        assert_eq!(sched.voluntary_ctxt_switches, 150);
+        assert_eq!(sched.nonvoluntary_ctxt_switches, 7);
+        assert_eq!(sched.minor_faults, 311);
+        assert_eq!(sched.major_faults, 2);
+        assert_eq!(sched.run_delay, Some(2_000_000));
+        assert_eq!(sched.timeslices, Some(40));
+
+        // 没有schedstat
+        let sched = parse_sched(STATUS, STAT, None).unwrap();
+        assert_eq!((sched.run_delay, sched.timeslices), (None, None));
+        assert!(parse_sched("Name:\tweird\n", STAT, None).is_err());
+    }
+
+    #[test]
+    fn regular_files() {
+        assert!(is_regular_file(Path::new("/var/log/app.log")));
+        assert!(!is_regular_file(Path::new("socket:[12345]")));
+        assert!(!is_regular_file(Path::new("anon_inode:[eventfd]")));
+        assert!(!is_regular_file(Path::new("/proc/1/stat")));
+        assert!(!is_regular_file(Path::new("/sys/fs/cgroup/memory.stat")));
+        assert!(!is_regular_file(Path::new("/tmp/scratch (deleted)")));
+    }
+
+    // 包含到exit_code为止的全部52个字段
+    fn zombie_stat(exit_code: i32) -> String {
+        let mut fields = vec!["0"; 49];
+        fields[0] = "Z";
+        format!("1234 (a b) c)) {} {}\n", fields.join(" "), exit_code)
+    }
+
+    #[test]
+    fn exit_status_fields() {
+        assert_eq!(
+            parse_exit_status(&zombie_stat(3 << 8)).unwrap(),
+            (Some(3), None)
+        );
+        assert_eq!(parse_exit_status(&zombie_stat(0)).unwrap(), (Some(0), None));
+        // 被SIGKILL结束, 带core dump标志
+        assert_eq!(
+            parse_exit_status(&zombie_stat(0x80 | 9)).unwrap(),
+            (None, Some(9))
+        );
+        // 旧内核没有exit_code字段
+        assert!(parse_exit_status(STAT).is_err());
+    }
+
+    #[test]
+    fn owner_uses_real_ids() {
+        let status = "Name:\tweird\nUid:\t1000\t0\t0\t0\nGid:\t100\t0\t0\t0\n";
+        assert_eq!(parse_owner(status).unwrap(), (1000, 100));
+        assert!(parse_owner(STATUS).is_err());
+    }
+
+    #[test]
+    fn account_names() {
+        let passwd = "root:x:0:0:root:/root:/bin/bash\n\
+                      # comment\n\
+                      broken\n\
+                      app:x:1000:100::/home/app:/bin/sh\n";
+        assert_eq!(parse_account_name(passwd, 0).as_deref(), Some("root"));
+        assert_eq!(parse_account_name(passwd, 1000).as_deref(), Some("app"));
+        assert_eq!(parse_account_name(passwd, 100), None);
+        assert_eq!(parse_account_name("", 0), None);
+    }
+}
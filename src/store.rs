@@ -1,3 +1,4 @@
+use std::io::SeekFrom;
 use std::mem::size_of;
 use std::path::Path;
 
@@ -5,21 +6,39 @@ use integer_encoding::{VarIntAsyncReader, VarIntAsyncWriter};
 use once_cell::sync::Lazy;
 use smallvec::SmallVec;
 use tokio::fs::{File, OpenOptions};
-use tokio::io::{AsyncReadExt, AsyncWriteExt, BufStream};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufStream};
 
-use crate::data::{Data, NetIo};
+use crate::data::{Data, DataFdV1, DataV1, Header, NetIo, Record, RecordV1};
 
 pub static BINCODE_CONFIG: Lazy<bincode::config::Configuration> =
     Lazy::new(bincode::config::standard);
 
+// 中间文件以MAGIC和Header开头, 之后是带长度的Record.
+// 旧版本的文件没有文件头, 只有带长度的Data. 长度不会是0, 所以第一个字节是0的就是新版本
+const MAGIC: &[u8; 13] = b"\0clairvoyance";
+// 2: Io的字段重新命名并增加了cancelled_write_bytes
+pub const VERSION: u32 = 2;
+
+// 编码后的一条记录, 通常不需要分配
+type RecordBuf = SmallVec<
+    [u8; size_of::<Data>()
+        + if cfg!(target_os = "linux") {
+            size_of::<NetIo>() * 3
+        } else {
+            0
+        }],
+>;
+
 // 存储数据到中间文件的流
 pub struct StoreStream {
     file: BufStream<File>,
+    // 旧版本的文件为None
+    header: Option<Header>,
 }
 
 impl StoreStream {
     // 创建并打开一个新的中间文件和存储流
-    pub async fn create<P>(path: P) -> anyhow::Result<Self>
+    pub async fn create<P>(path: P, header: Header) -> anyhow::Result<Self>
     where
         P: AsRef<Path>,
     {
@@ -29,9 +48,15 @@ impl StoreStream {
             .create(true)
             .open(path)
             .await?;
-        Ok(StoreStream {
+        let mut stream = StoreStream {
             file: BufStream::new(file),
-        })
+            header: None,
+        };
+
+        stream.file.write_all(MAGIC).await?;
+        stream.write_encoded(&header).await?;
+        stream.header = Some(header);
+        Ok(stream)
     }
 
     // 打开一个已经存在的中间文件和存储流
@@ -39,24 +64,46 @@ impl StoreStream {
     where
         P: AsRef<Path>,
     {
-        let file = OpenOptions::new().read(true).open(path).await?;
-        Ok(StoreStream {
+        let mut file = OpenOptions::new().read(true).open(path).await?;
+
+        let mut magic = [0u8; MAGIC.len()];
+        let is_legacy = match file.read_exact(&mut magic).await {
+            Ok(_) => &magic != MAGIC,
+            Err(err) if matches!(err.kind(), std::io::ErrorKind::UnexpectedEof) => true,
+            Err(err) => return Err(err.into()),
+        };
+        if is_legacy {
+            file.seek(SeekFrom::Start(0)).await?;
+        }
+
+        let mut stream = StoreStream {
             file: BufStream::new(file),
-        })
+            header: None,
+        };
+        if !is_legacy {
+            stream.header = Some(
+                stream
+                    .read_decoded()
+                    .await?
+                    .ok_or_else(|| anyhow::anyhow!("missing header"))?,
+            );
+        }
+        Ok(stream)
     }
 
-    pub async fn write(&mut self, data: &Data) -> anyhow::Result<()> {
-        let mut buf = SmallVec::<
-            [u8; size_of::<Data>()
-                + if cfg!(target_os = "linux") {
-                    size_of::<NetIo>() * 3
-                } else {
-                    0
-                }],
-        >::new_const();
-        bincode::encode_into_std_write(data, &mut buf, *BINCODE_CONFIG)?;
+    pub fn header(&self) -> Option<&Header> {
+        self.header.as_ref()
+    }
+
+    pub async fn write(&mut self, record: &Record) -> anyhow::Result<()> {
+        self.write_encoded(record).await
+    }
 
-        // Data的长度
+    async fn write_encoded<T: bincode::Encode>(&mut self, value: &T) -> anyhow::Result<()> {
+        let mut buf = RecordBuf::new_const();
+        bincode::encode_into_std_write(value, &mut buf, *BINCODE_CONFIG)?;
+
+        // Record的长度
         self.file.write_varint_async(buf.len()).await?;
         self.file.write_all(&buf).await?;
         Ok(())
@@ -68,7 +115,28 @@ impl StoreStream {
         Ok(())
     }
 
-    pub async fn read(&mut self) -> anyhow::Result<Option<Data>> {
+    // 旧版本的记录在读取时转换为当前版本
+    pub async fn read(&mut self) -> anyhow::Result<Option<Record>> {
+        match self.header.as_ref().map(|header| header.version) {
+            Some(VERSION) => self.read_decoded().await,
+            Some(1) => Ok(self.read_decoded::<RecordV1>().await?.map(Record::from)),
+            Some(version) => anyhow::bail!("unsupported file version {}", version),
+            None => match self.read_buf().await? {
+                Some(buf) => Ok(Some(Record::Data(decode_legacy(&buf)?))),
+                None => Ok(None),
+            },
+        }
+    }
+
+    async fn read_decoded<T: bincode::Decode>(&mut self) -> anyhow::Result<Option<T>> {
+        match self.read_buf().await? {
+            Some(buf) => Ok(Some(bincode::decode_from_slice(&buf, *BINCODE_CONFIG)?.0)),
+            None => Ok(None),
+        }
+    }
+
+    // 一条记录的内容, 文件结束时为None
+    async fn read_buf(&mut self) -> anyhow::Result<Option<RecordBuf>> {
         let size = match self.file.read_varint_async().await {
             Ok(n) => n,
             Err(err) => {
@@ -80,19 +148,237 @@ impl StoreStream {
             }
         };
 
-        let mut buf = SmallVec::<
-            [u8; size_of::<Data>()
-                + if cfg!(target_os = "linux") {
-                    size_of::<NetIo>() * 3
-                } else {
-                    0
-                }],
-        >::new_const();
+        let mut buf = RecordBuf::new_const();
         buf.reserve_exact(size);
         buf.resize(size, 0);
 
         self.file.read_exact(&mut buf).await?;
 
-        Ok(Some(bincode::decode_from_slice(&buf, *BINCODE_CONFIG)?.0))
+        Ok(Some(buf))
+    }
+}
+
+// 没有文件头的旧文件的Data. 最早记录fd的版本的Fd布局不同, 这种记录在fd之后结束,
+// 所以先按那时的布局读取, 不能恰好读完整条记录时再按之后的布局读取
+fn decode_legacy(buf: &[u8]) -> anyhow::Result<Data> {
+    match bincode::decode_from_slice::<DataFdV1, _>(buf, *BINCODE_CONFIG) {
+        Ok((data, len)) if len == buf.len() => Ok(data.0),
+        _ => Ok(
+            bincode::decode_from_slice::<DataV1, _>(buf, *BINCODE_CONFIG)?
+                .0
+                 .0,
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::data::{CpuTime, CpuUsage, Fd, Limits, Memory, Sched};
+
+    // 旧文件中Data的前几个字段, Io为版本2之前的布局
+    fn prefix() -> (
+        Memory,
+        CpuTime,
+        CpuUsage,
+        (u64, u64, Option<u64>, Option<u64>, Option<u64>, Option<u64>),
+        HashMap<String, NetIo>,
+        Option<u64>,
+        Option<Sched>,
+    ) {
+        (
+            Memory {
+                rss: 100,
+                vms: 200,
+                shared: None,
+                text: None,
+                data: None,
+            },
+            CpuTime {
+                user: 1.0,
+                system: 2.0,
+            },
+            CpuUsage(3.0),
+            (10, 20, Some(30), Some(40), None, None),
+            HashMap::new(),
+            Some(1000),
+            None,
+        )
+    }
+
+    fn encode(value: impl bincode::Encode) -> Vec<u8> {
+        bincode::encode_to_vec(value, *BINCODE_CONFIG).unwrap()
+    }
+
+    #[test]
+    fn legacy_fd_with_limits() {
+        let tcp = HashMap::from([("ESTABLISHED".to_owned(), 2u64)]);
+        let fd = (
+            5u64,
+            1u64,
+            2u64,
+            1u64,
+            1u64,
+            0u64,
+            Some(1024u64),
+            Some(4096u64),
+            tcp,
+            HashMap::<String, u64>::new(),
+        );
+        let buf = encode((prefix(), Some(fd)));
+
+        let data = decode_legacy(&buf).unwrap();
+        assert_eq!(data.io.storage_written, 10);
+        assert_eq!(data.time, Some(1000));
+        let fd = data.fd.unwrap();
+        assert_eq!((fd.total, fd.socket), (5, 2));
+        assert_eq!(fd.tcp["ESTABLISHED"], 2);
+        assert_eq!(data.limits.unwrap().nofile, Some(1024));
+    }
+
+    #[test]
+    fn legacy_fd_and_limits() {
+        let fd = Fd {
+            total: 3,
+            tcp: HashMap::from([("LISTEN".to_owned(), 1)]),
+            ..Fd::default()
+        };
+        let limits = Limits {
+            address_space: None,
+            data: None,
+            nofile: Some(2048),
+            nproc: Some(100),
+            memory_max: None,
+            cpu_max: None,
+        };
+        let buf = encode((prefix(), Some(fd), Some(limits.clone())));
+
+        let data = decode_legacy(&buf).unwrap();
+        let fd = data.fd.unwrap();
+        assert_eq!(fd.total, 3);
+        assert_eq!(fd.tcp["LISTEN"], 1);
+        assert_eq!(data.limits, Some(limits));
+    }
+
+    #[test]
+    fn legacy_without_fd() {
+        let data = decode_legacy(&encode(prefix())).unwrap();
+        assert_eq!(data.memory.rss, 100);
+        assert!(data.fd.is_none() && data.limits.is_none());
+    }
+
+    // 每条记录前加上长度
+    fn with_length(records: &[Vec<u8>]) -> Vec<u8> {
+        use integer_encoding::VarInt;
+
+        records
+            .iter()
+            .flat_map(|record| [record.len().encode_var_vec(), record.clone()])
+            .flatten()
+            .collect()
+    }
+
+    fn data() -> Data {
+        decode_legacy(&encode(prefix())).unwrap()
+    }
+
+    async fn read_data(stream: &mut StoreStream) -> Data {
+        match stream.read().await.unwrap() {
+            Some(Record::Data(data)) => data,
+            other => panic!("expected data, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn header_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data");
+        let header = Header {
+            version: VERSION,
+            cores: 8,
+            process: None,
+        };
+        let mut stream = StoreStream::create(&path, header).await.unwrap();
+        stream.write(&Record::Data(data())).await.unwrap();
+        stream.flush().await.unwrap();
+        drop(stream);
+
+        assert!(std::fs::read(&path).unwrap().starts_with(MAGIC));
+
+        let mut stream = StoreStream::open(&path).await.unwrap();
+        let header = stream.header().unwrap();
+        assert_eq!((header.version, header.cores), (VERSION, 8));
+        assert_eq!(read_data(&mut stream).await.memory.rss, 100);
+        assert!(stream.read().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn legacy_file_without_header() {
+        // 旧文件只有带长度的Data
+        let fd = (
+            1u64,
+            1u64,
+            0u64,
+            0u64,
+            0u64,
+            0u64,
+            Some(1024u64),
+            None::<u64>,
+            HashMap::<String, u64>::new(),
+            HashMap::<String, u64>::new(),
+        );
+        let content = with_length(&[encode(prefix()), encode((prefix(), Some(fd)))]);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data");
+        std::fs::write(&path, content).unwrap();
+
+        let mut stream = StoreStream::open(&path).await.unwrap();
+        assert!(stream.header().is_none());
+        assert!(read_data(&mut stream).await.fd.is_none());
+        let data = read_data(&mut stream).await;
+        assert_eq!(data.fd.unwrap().total, 1);
+        assert_eq!(data.limits.unwrap().nofile, Some(1024));
+        assert!(stream.read().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn version_1_io() {
+        // 版本1的Header没有process, Record::Data中Io为旧的布局
+        let mut content = MAGIC.to_vec();
+        content.extend(with_length(&[
+            encode((1u32, 4u32)),
+            encode((0u32, prefix())),
+        ]));
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data");
+        std::fs::write(&path, content).unwrap();
+
+        let mut stream = StoreStream::open(&path).await.unwrap();
+        assert_eq!(stream.header().unwrap().version, 1);
+        assert!(stream.header().unwrap().process.is_none());
+        let io = read_data(&mut stream).await.io;
+        assert_eq!((io.storage_written, io.storage_read), (10, 20));
+        assert_eq!((io.chars_written, io.chars_read), (Some(30), Some(40)));
+        assert_eq!((io.syscall_written, io.syscall_read), (None, None));
+        assert_eq!(io.cancelled_write_bytes, None);
+        assert!(stream.read().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn shorter_than_magic() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data");
+
+        // 不到MAGIC长度的文件按旧文件读取
+        std::fs::write(&path, &MAGIC[..5]).unwrap();
+        let stream = StoreStream::open(&path).await.unwrap();
+        assert!(stream.header().is_none());
+
+        std::fs::write(&path, b"").unwrap();
+        let mut stream = StoreStream::open(&path).await.unwrap();
+        assert!(stream.header().is_none());
+        assert!(stream.read().await.unwrap().is_none());
     }
 }
@@ -0,0 +1,474 @@
+// 管理全部记录的生命周期: 可以随时加入新的记录, 单独取消某个记录, 关机时等待仍在运行的记录结束
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::{watch, Notify};
+
+// 记录结束的原因
+#[derive(Debug, Clone)]
+pub enum ExitReason {
+    // 进程退出或者cgroup被删除
+    TargetExited,
+    // 满足了`--samples`, `--until-idle`等结束条件
+    Finished(String),
+    // 被单独取消
+    Cancelled,
+    // 关机
+    Shutdown,
+    // 写入中间文件失败, 记录不完整
+    Store(String),
+    // 没有报告原因, 例如创建之后还没有开始记录就被丢弃
+    Dropped,
+}
+
+impl Display for ExitReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExitReason::TargetExited => write!(f, "target exited"),
+            ExitReason::Finished(reason) => write!(f, "finished: {}", reason),
+            ExitReason::Cancelled => write!(f, "cancelled"),
+            ExitReason::Shutdown => write!(f, "shutdown"),
+            ExitReason::Store(err) => write!(f, "store error: {}", err),
+            ExitReason::Dropped => write!(f, "dropped"),
+        }
+    }
+}
+
+// 记录结束时什么时候关机
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StopPolicy {
+    // 任意一个记录结束
+    #[default]
+    FirstExit,
+    // 全部记录都结束
+    AllExit,
+    // 只在收到信号或者`shutdown`时, 以及全部记录结束并且有记录达到了`--samples`/`--until-idle`时
+    Signal,
+}
+
+impl FromStr for StopPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "first" => Ok(StopPolicy::FirstExit),
+            "all" => Ok(StopPolicy::AllExit),
+            "signal" => Ok(StopPolicy::Signal),
+            _ => Err(format!(
+                "unknown stop policy {}, expected first, all or signal",
+                s
+            )),
+        }
+    }
+}
+
+// 一个记录结束时的结果
+#[derive(Debug, Clone)]
+pub struct JoinResult {
+    pub id: u64,
+    pub name: String,
+    pub reason: ExitReason,
+}
+
+#[derive(Clone)]
+pub struct Supervisor {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    next_id: AtomicU64,
+    shutting_down: AtomicBool,
+    // 正在运行的记录, 发送true让它结束
+    live: Mutex<HashMap<u64, watch::Sender<bool>>>,
+    // 正在运行的记录的数量
+    live_count: watch::Sender<usize>,
+    results: Mutex<Vec<JoinResult>>,
+    // 有记录结束
+    exited: Notify,
+    // `shutdown`主动请求关机
+    requested: Notify,
+}
+
+pub struct ShutdownGuard {
+    id: u64,
+    inner: Arc<Inner>,
+    stop: watch::Receiver<bool>,
+    result: Mutex<Option<(String, ExitReason)>>,
+}
+
+impl Supervisor {
+    pub fn new() -> Supervisor {
+        Supervisor {
+            inner: Arc::new(Inner {
+                next_id: AtomicU64::new(0),
+                shutting_down: AtomicBool::new(false),
+                live: Mutex::new(HashMap::new()),
+                live_count: watch::channel(0).0,
+                results: Mutex::new(Vec::new()),
+                exited: Notify::const_new(),
+                requested: Notify::const_new(),
+            }),
+        }
+    }
+
+    // 加入一个新的记录, 关机之后不能再加入
+    pub fn start(&self) -> anyhow::Result<ShutdownGuard> {
+        let mut live = self.inner.live.lock().unwrap();
+        if self.inner.shutting_down.load(Ordering::SeqCst) {
+            anyhow::bail!("cannot start a monitor during shutdown");
+        }
+        let id = self.inner.next_id.fetch_add(1, Ordering::SeqCst);
+        let (sender, stop) = watch::channel(false);
+        live.insert(id, sender);
+        self.inner.live_count.send_replace(live.len());
+
+        Ok(ShutdownGuard {
+            id,
+            inner: self.inner.clone(),
+            stop,
+            result: Mutex::new(None),
+        })
+    }
+
+    // 让一个记录结束, 记录不存在或者已经结束时返回false
+    pub fn cancel(&self, id: u64) -> bool {
+        match self.inner.live.lock().unwrap().get(&id) {
+            Some(sender) => {
+                sender.send_replace(true);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn live(&self) -> usize {
+        self.inner.live.lock().unwrap().len()
+    }
+
+    // 已经结束的记录
+    pub fn results(&self) -> Vec<JoinResult> {
+        self.inner.results.lock().unwrap().clone()
+    }
+
+    // 按照`policy`等待记录结束
+    async fn exited(&self, policy: StopPolicy) {
+        match policy {
+            StopPolicy::FirstExit => self.inner.exited.notified().await,
+            StopPolicy::AllExit => loop {
+                self.inner.exited.notified().await;
+                if self.live() == 0 {
+                    break;
+                }
+            },
+            // 进程退出不关机, 但是`--samples`/`--until-idle`结束了全部记录时不再等待信号
+            StopPolicy::Signal => loop {
+                self.inner.exited.notified().await;
+                if self.live() == 0
+                    && self
+                        .results()
+                        .iter()
+                        .any(|result| matches!(result.reason, ExitReason::Finished(_)))
+                {
+                    break;
+                }
+            },
+        }
+    }
+
+    // 让`wait_shutdown`开始关机, 和ctrl+c一样
+    pub fn shutdown(&self) {
+        self.inner.requested.notify_one();
+    }
+
+    // 等到收到信号, `shutdown`或者按照`policy`有记录结束, 然后通知全部记录结束.
+    // 返回每个记录的结果, `timeout`内没有结束的记录不在其中
+    pub async fn wait_shutdown(&self, timeout: Duration, policy: StopPolicy) -> Vec<JoinResult> {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate() => {}
+            _ = self.inner.requested.notified() => {}
+            _ = self.exited(policy) => {}
+        }
+
+        let waiting = {
+            let live = self.inner.live.lock().unwrap();
+            self.inner.shutting_down.store(true, Ordering::SeqCst);
+            for sender in live.values() {
+                sender.send_replace(true);
+            }
+            live.len()
+        };
+
+        let mut live_count = self.inner.live_count.subscribe();
+        let finished = tokio::time::timeout(timeout, async {
+            while *live_count.borrow() > 0 {
+                if live_count.changed().await.is_err() {
+                    break;
+                }
+            }
+        })
+        .await
+        .is_ok();
+        let remaining = self.live();
+        if waiting > 0 {
+            log::info!(
+                "{}/{} monitors finished within {:?}",
+                waiting - remaining,
+                waiting,
+                timeout
+            );
+        }
+        if !finished {
+            log::warn!("{} monitors did not finish in time", remaining);
+        }
+
+        self.results()
+    }
+}
+
+// SIGTERM和SIGQUIT, 例如systemd和docker停止时
+async fn terminate() {
+    cfg_if::cfg_if! {
+        if #[cfg(unix)] {
+            use tokio::signal::unix::{signal, SignalKind};
+
+            match (signal(SignalKind::terminate()), signal(SignalKind::quit())) {
+                (Ok(mut term), Ok(mut quit)) => {
+                    tokio::select! {
+                        _ = term.recv() => {}
+                        _ = quit.recv() => {}
+                    }
+                }
+                _ => futures::future::pending().await,
+            }
+        } else {
+            futures::future::pending().await
+        }
+    }
+}
+
+impl ShutdownGuard {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    // 等待关机或者取消的通知
+    pub async fn notified(&self) {
+        let mut stop = self.stop.clone();
+        while !*stop.borrow() {
+            if stop.changed().await.is_err() {
+                futures::future::pending::<()>().await;
+            }
+        }
+    }
+
+    // 收到的是关机还是单独取消
+    pub fn stop_reason(&self) -> ExitReason {
+        if self.inner.shutting_down.load(Ordering::SeqCst) {
+            ExitReason::Shutdown
+        } else {
+            ExitReason::Cancelled
+        }
+    }
+
+    // 记录结束的原因, 在ShutdownGuard drop时作为结果
+    pub fn report(&self, name: String, reason: ExitReason) {
+        *self.result.lock().unwrap() = Some((name, reason));
+    }
+}
+
+impl Drop for ShutdownGuard {
+    fn drop(&mut self) {
+        let (name, reason) = self
+            .result
+            .lock()
+            .unwrap()
+            .take()
+            .unwrap_or_else(|| (self.id.to_string(), ExitReason::Dropped));
+        log::info!("monitor {} ({}) exited: {}", self.id, name, reason);
+
+        {
+            let mut live = self.inner.live.lock().unwrap();
+            live.remove(&self.id);
+            self.inner.live_count.send_replace(live.len());
+        }
+        self.inner.results.lock().unwrap().push(JoinResult {
+            id: self.id,
+            name,
+            reason,
+        });
+        self.inner.exited.notify_one();
+    }
+}
+
+impl Default for Supervisor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reasons(supervisor: &Supervisor) -> Vec<(String, String)> {
+        supervisor
+            .results()
+            .into_iter()
+            .map(|result| (result.name, result.reason.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn start_and_drop() {
+        let supervisor = Supervisor::new();
+        let first = supervisor.start().unwrap();
+        let second = supervisor.start().unwrap();
+        assert_ne!(first.id(), second.id());
+        assert_eq!(supervisor.live(), 2);
+
+        // 没有报告原因
+        let id = first.id();
+        drop(first);
+        assert_eq!(supervisor.live(), 1);
+        assert_eq!(
+            reasons(&supervisor),
+            [(id.to_string(), "dropped".to_owned())]
+        );
+
+        second.report("1234".to_owned(), ExitReason::TargetExited);
+        drop(second);
+        assert_eq!(supervisor.live(), 0);
+        assert_eq!(
+            reasons(&supervisor)[1],
+            ("1234".to_owned(), "target exited".to_owned())
+        );
+    }
+
+    #[tokio::test]
+    async fn cancel_one() {
+        let supervisor = Supervisor::new();
+        let first = supervisor.start().unwrap();
+        let second = supervisor.start().unwrap();
+
+        assert!(supervisor.cancel(first.id()));
+        tokio::time::timeout(Duration::from_secs(5), first.notified())
+            .await
+            .unwrap();
+        assert!(matches!(first.stop_reason(), ExitReason::Cancelled));
+        // 其他记录不受影响
+        assert!(!*second.stop.borrow());
+
+        let id = first.id();
+        drop(first);
+        assert!(!supervisor.cancel(id));
+        assert!(!supervisor.cancel(100));
+    }
+
+    #[tokio::test]
+    async fn shutdown_waits_for_monitors() {
+        let supervisor = Supervisor::new();
+        for name in ["a", "b"] {
+            let guard = supervisor.start().unwrap();
+            tokio::spawn(async move {
+                guard.notified().await;
+                guard.report(name.to_owned(), guard.stop_reason());
+            });
+        }
+
+        supervisor.shutdown();
+        let mut results = supervisor
+            .wait_shutdown(Duration::from_secs(5), StopPolicy::FirstExit)
+            .await;
+        results.sort_by(|a, b| a.name.cmp(&b.name));
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].name, "a");
+        assert!(matches!(results[1].reason, ExitReason::Shutdown));
+
+        // 关机之后不能再加入
+        assert!(supervisor.start().is_err());
+    }
+
+    #[tokio::test]
+    async fn shutdown_on_first_exit() {
+        let supervisor = Supervisor::new();
+        let exited = supervisor.start().unwrap();
+        let running = supervisor.start().unwrap();
+        exited.report("exited".to_owned(), ExitReason::TargetExited);
+        drop(exited);
+
+        // 在timeout内没有结束的记录不在结果中
+        let results = tokio::time::timeout(
+            Duration::from_secs(5),
+            supervisor.wait_shutdown(Duration::from_millis(50), StopPolicy::FirstExit),
+        )
+        .await
+        .unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].name, "exited");
+        assert_eq!(supervisor.live(), 1);
+        assert!(*running.stop.borrow());
+    }
+
+    #[tokio::test]
+    async fn all_exit_waits_for_every_monitor() {
+        let supervisor = Supervisor::new();
+        let first = supervisor.start().unwrap();
+        let second = supervisor.start().unwrap();
+        drop(first);
+
+        // 还有记录在运行
+        let waited = tokio::time::timeout(
+            Duration::from_millis(200),
+            supervisor.wait_shutdown(Duration::from_secs(1), StopPolicy::AllExit),
+        )
+        .await;
+        assert!(waited.is_err());
+
+        drop(second);
+        let results = tokio::time::timeout(
+            Duration::from_secs(5),
+            supervisor.wait_shutdown(Duration::from_secs(1), StopPolicy::AllExit),
+        )
+        .await
+        .unwrap();
+        assert_eq!(results.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn signal_policy_stops_when_finished() {
+        let supervisor = Supervisor::new();
+        let guard = supervisor.start().unwrap();
+        guard.report(
+            "1".to_owned(),
+            ExitReason::Finished("reached 1 samples".to_owned()),
+        );
+        drop(guard);
+
+        let results = tokio::time::timeout(
+            Duration::from_secs(5),
+            supervisor.wait_shutdown(Duration::from_secs(1), StopPolicy::Signal),
+        )
+        .await
+        .unwrap();
+        assert_eq!(results.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn signal_policy_ignores_exits() {
+        let supervisor = Supervisor::new();
+        drop(supervisor.start().unwrap());
+
+        // 进程退出时继续等待信号
+        let waited = tokio::time::timeout(
+            Duration::from_millis(200),
+            supervisor.wait_shutdown(Duration::from_secs(1), StopPolicy::Signal),
+        )
+        .await;
+        assert!(waited.is_err());
+    }
+}
@@ -0,0 +1,326 @@
+use std::path::Path;
+use std::str::FromStr;
+
+use plotters::prelude::{Palette as _, Palette99, RGBColor};
+use serde::Deserialize;
+
+// 图表的外观, 见`crate::draw`
+#[derive(Clone)]
+pub struct Theme {
+    // 像素
+    pub size: (u32, u32),
+    pub background: RGBColor,
+    // 文字, 坐标轴和标记的颜色
+    pub foreground: RGBColor,
+    // 曲线依次使用的颜色
+    pub palette: Vec<RGBColor>,
+    // 进程数据曲线的宽度, 系统数据和资源限制的曲线更细
+    pub line_width: u32,
+    pub font: String,
+    // 坐标轴上字节数的单位
+    pub units: Units,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme::light()
+    }
+}
+
+impl Theme {
+    pub fn light() -> Theme {
+        Theme {
+            size: (1920, 1080),
+            background: RGBColor(255, 255, 255),
+            foreground: RGBColor(0, 0, 0),
+            palette: Palette::Default.colors(),
+            line_width: 3,
+            font: "sans-serif".to_owned(),
+            units: Units::Binary,
+        }
+    }
+
+    pub fn dark() -> Theme {
+        Theme {
+            background: RGBColor(30, 30, 30),
+            foreground: RGBColor(220, 220, 220),
+            ..Theme::light()
+        }
+    }
+
+    // 内置的主题名, 或者json格式的主题文件
+    pub fn load(name: &str) -> anyhow::Result<Theme> {
+        match name {
+            "light" => Ok(Theme::light()),
+            "dark" => Ok(Theme::dark()),
+            path => Theme::from_file(path),
+        }
+    }
+
+    // 命令行的选项覆盖主题中的值
+    pub fn with(mut self, overrides: Overrides) -> Theme {
+        if let Some(size) = overrides.size {
+            self.size = size;
+        }
+        if let Some(palette) = overrides.palette {
+            self.palette = palette.colors();
+        }
+        if let Some(width) = overrides.line_width {
+            self.line_width = width;
+        }
+        if let Some(font) = overrides.font {
+            self.font = font;
+        }
+        if let Some(units) = overrides.units {
+            self.units = units;
+        }
+        self
+    }
+
+    // 文件中没有的项使用`base`主题的值
+    fn from_file<P: AsRef<Path>>(path: P) -> anyhow::Result<Theme> {
+        let path = path.as_ref();
+        let file: ThemeFile = serde_json::from_slice(&std::fs::read(path)?)
+            .map_err(|e| anyhow::anyhow!("invalid theme file {}: {}", path.display(), e))?;
+
+        let mut theme = match file.base {
+            Base::Light => Theme::light(),
+            Base::Dark => Theme::dark(),
+        };
+        if let Some(size) = file.size {
+            anyhow::ensure!(size.0 > 0 && size.1 > 0, "invalid size {:?}", size);
+            theme.size = size;
+        }
+        if let Some(color) = file.background {
+            theme.background = parse_color(&color)?;
+        }
+        if let Some(color) = file.foreground {
+            theme.foreground = parse_color(&color)?;
+        }
+        match file.palette {
+            Some(PaletteFile::Builtin(palette)) => theme.palette = palette.colors(),
+            Some(PaletteFile::Colors(colors)) => {
+                theme.palette = colors
+                    .iter()
+                    .map(|color| parse_color(color))
+                    .collect::<anyhow::Result<_>>()?;
+                anyhow::ensure!(!theme.palette.is_empty(), "empty palette");
+            }
+            None => {}
+        }
+        if let Some(width) = file.line_width {
+            anyhow::ensure!(width > 0, "invalid line_width 0");
+            theme.line_width = width;
+        }
+        if let Some(font) = file.font {
+            theme.font = font;
+        }
+        if let Some(units) = file.units {
+            theme.units = units;
+        }
+        Ok(theme)
+    }
+}
+
+// 命令行中设置的主题选项, 见`Theme::with`
+#[derive(Default)]
+pub struct Overrides {
+    pub size: Option<(u32, u32)>,
+    pub palette: Option<Palette>,
+    pub line_width: Option<u32>,
+    pub font: Option<String>,
+    pub units: Option<Units>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ThemeFile {
+    #[serde(default)]
+    base: Base,
+    size: Option<(u32, u32)>,
+    // #rrggbb
+    background: Option<String>,
+    foreground: Option<String>,
+    // 内置调色板的名称, 或者颜色的列表
+    palette: Option<PaletteFile>,
+    line_width: Option<u32>,
+    font: Option<String>,
+    units: Option<Units>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+enum Base {
+    #[default]
+    Light,
+    Dark,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PaletteFile {
+    Builtin(Palette),
+    Colors(Vec<String>),
+}
+
+// 字节数的单位是1024的倍数(KiB, MiB)还是1000的倍数(KB, MB)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Units {
+    Binary,
+    Decimal,
+}
+
+fn parse_color(s: &str) -> anyhow::Result<RGBColor> {
+    let hex = s
+        .strip_prefix('#')
+        // 切片之前确认都是单字节的字符
+        .filter(|hex| hex.len() == 6 && hex.is_ascii())
+        .ok_or_else(|| anyhow::anyhow!("invalid color {}, expected #rrggbb", s))?;
+    let channel = |i: usize| {
+        u8::from_str_radix(&hex[i..i + 2], 16)
+            .map_err(|_| anyhow::anyhow!("invalid color {}, expected #rrggbb", s))
+    };
+    Ok(RGBColor(channel(0)?, channel(2)?, channel(4)?))
+}
+
+// 内置的调色板
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Palette {
+    Default,
+    // Okabe & Ito, 色盲友好
+    OkabeIto,
+    // Paul Tol的bright, 色盲友好
+    Tol,
+}
+
+impl Palette {
+    pub fn colors(&self) -> Vec<RGBColor> {
+        let hex = |colors: &[u32]| {
+            colors
+                .iter()
+                .map(|c| RGBColor((c >> 16) as u8, (c >> 8) as u8, *c as u8))
+                .collect()
+        };
+        match self {
+            Palette::Default => Palette99::COLORS
+                .iter()
+                .map(|(r, g, b)| RGBColor(*r, *g, *b))
+                .collect(),
+            Palette::OkabeIto => hex(&[
+                0xe69f00, 0x56b4e9, 0x009e73, 0xf0e442, 0x0072b2, 0xd55e00, 0xcc79a7, 0x999999,
+            ]),
+            Palette::Tol => hex(&[
+                0x4477aa, 0xee6677, 0x228833, 0xccbb44, 0x66ccee, 0xaa3377, 0xbbbbbb,
+            ]),
+        }
+    }
+}
+
+impl FromStr for Palette {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "default" => Ok(Palette::Default),
+            "okabe-ito" => Ok(Palette::OkabeIto),
+            "tol" => Ok(Palette::Tol),
+            _ => Err(format!(
+                "unknown palette {}, expected default, okabe-ito or tol",
+                s
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(json: &str) -> anyhow::Result<Theme> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("theme.json");
+        std::fs::write(&path, json).unwrap();
+        Theme::load(path.to_str().unwrap())
+    }
+
+    #[test]
+    fn colors() {
+        assert_eq!(parse_color("#ff8000").unwrap(), RGBColor(255, 128, 0));
+        assert_eq!(parse_color("#0A0b0C").unwrap(), RGBColor(10, 11, 12));
+        assert!(parse_color("ff8000").is_err());
+        assert!(parse_color("#ff800").is_err());
+        assert!(parse_color("#gg0000").is_err());
+        // 6个字节但不是6个字符, 不能按字节切片
+        assert!(parse_color("#ä000").is_err());
+        assert!(parse_color("#€000").is_err());
+    }
+
+    #[test]
+    fn theme_file() {
+        let theme = load(
+            r##"{
+                "base": "dark",
+                "size": [1280, 720],
+                "foreground": "#ffffff",
+                "palette": ["#ff0000", "#00ff00"],
+                "line_width": 2,
+                "units": "decimal"
+            }"##,
+        )
+        .unwrap();
+        assert_eq!(theme.size, (1280, 720));
+        // 没有设置的项使用base主题的值
+        assert_eq!(theme.background, Theme::dark().background);
+        assert_eq!(theme.foreground, RGBColor(255, 255, 255));
+        assert_eq!(theme.palette, [RGBColor(255, 0, 0), RGBColor(0, 255, 0)]);
+        assert_eq!(theme.line_width, 2);
+        assert_eq!(theme.font, "sans-serif");
+        assert_eq!(theme.units, Units::Decimal);
+
+        let theme = load(r#"{"palette": "okabe-ito"}"#).unwrap();
+        assert_eq!(theme.background, Theme::light().background);
+        assert_eq!(theme.palette, Palette::OkabeIto.colors());
+        assert_eq!(theme.units, Units::Binary);
+    }
+
+    #[test]
+    fn invalid_theme_file() {
+        assert!(load(r#"{"size": [0, 720]}"#).is_err());
+        assert!(load(r#"{"size": [0, 0]}"#).is_err());
+        assert!(load(r#"{"line_width": 0}"#).is_err());
+        assert!(load(r#"{"palette": []}"#).is_err());
+        assert!(load(r#"{"palette": "rainbow"}"#).is_err());
+        assert!(load(r#"{"background": "white"}"#).is_err());
+        assert!(load(r#"{"units": "metric"}"#).is_err());
+        assert!(load(r##"{"colour": "#ffffff"}"##).is_err());
+        assert!(Theme::load("/nonexistent/theme.json").is_err());
+    }
+
+    #[test]
+    fn options_override_theme() {
+        let theme = load(r#"{"base": "dark", "palette": "tol", "line_width": 2}"#)
+            .unwrap()
+            .with(Overrides {
+                size: Some((800, 600)),
+                palette: Some(Palette::OkabeIto),
+                ..Overrides::default()
+            });
+        assert_eq!(theme.size, (800, 600));
+        assert_eq!(theme.palette, Palette::OkabeIto.colors());
+        // 命令行中没有的选项保留主题文件中的值
+        assert_eq!(theme.line_width, 2);
+        assert_eq!(theme.background, Theme::dark().background);
+
+        let theme = Theme::dark().with(Overrides {
+            line_width: Some(5),
+            font: Some("serif".to_owned()),
+            units: Some(Units::Decimal),
+            ..Overrides::default()
+        });
+        assert_eq!((theme.line_width, theme.font.as_str()), (5, "serif"));
+        assert_eq!(theme.units, Units::Decimal);
+        assert_eq!(theme.palette, Palette::Default.colors());
+    }
+}
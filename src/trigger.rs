@@ -0,0 +1,177 @@
+// 触发模式: 平时只在内存中保留最近的采样, 满足条件时才把触发前后的一段时间写入中间文件
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::data::{Data, Limits, Record, Segment};
+
+#[derive(Debug, Clone, Copy)]
+pub struct Trigger {
+    // rss超过这个值, 字节
+    pub rss: Option<u64>,
+    // cpu使用率超过这个值持续`cpu_for`
+    pub cpu: Option<f32>,
+    pub cpu_for: Duration,
+    // 进程退出或者cgroup被删除
+    pub exit: bool,
+    // 保存触发之前和之后多长时间的采样
+    pub before: Duration,
+    pub after: Duration,
+}
+
+pub struct TriggerBuffer {
+    trigger: Trigger,
+    ring: VecDeque<(Instant, Record)>,
+    // 正在保存的段的结束时间, 期间再次触发会延长
+    until: Option<Instant>,
+    // cpu使用率开始超过阈值的时间
+    cpu_since: Option<Instant>,
+}
+
+impl TriggerBuffer {
+    pub fn new(trigger: Trigger) -> Self {
+        TriggerBuffer {
+            trigger,
+            ring: VecDeque::new(),
+            until: None,
+            cpu_since: None,
+        }
+    }
+
+    pub fn trigger(&self) -> &Trigger {
+        &self.trigger
+    }
+
+    // 这次采样满足的条件
+    pub fn check(&mut self, data: &Data) -> Option<String> {
+        if let Some(rss) = self.trigger.rss {
+            if data.memory.rss > rss {
+                return Some(format!("rss > {}", rss));
+            }
+        }
+        if let Some(cpu) = self.trigger.cpu {
+            if data.cpu_usage.0 > cpu {
+                let since = *self.cpu_since.get_or_insert_with(Instant::now);
+                if since.elapsed() >= self.trigger.cpu_for {
+                    return Some(format!("cpu > {}% for {:?}", cpu, self.trigger.cpu_for));
+                }
+            } else {
+                self.cpu_since = None;
+            }
+        }
+        None
+    }
+
+    // 触发后需要写入的记录: 新的段和缓冲区中的采样. 已经在保存时只延长结束时间
+    pub fn fire(&mut self, reason: String) -> Vec<Record> {
+        let now = Instant::now();
+        let recording = self.is_recording(now);
+        self.until = Some(now + self.trigger.after);
+        if recording {
+            return Vec::new();
+        }
+
+        log::info!("triggered: {}", reason);
+        std::iter::once(Record::Segment(Segment {
+            time: chrono::Utc::now().timestamp_millis() as u64,
+            reason,
+        }))
+        .chain(self.ring.drain(..).map(|(_, record)| record))
+        .collect()
+    }
+
+    // 正在保存时返回需要写入的记录, 否则放入缓冲区
+    pub fn buffer(&mut self, record: Record) -> Option<Record> {
+        let now = Instant::now();
+        if self.is_recording(now) {
+            return Some(record);
+        }
+        if self.until.take().is_some() {
+            log::info!("segment finished");
+        }
+
+        self.ring.push_back((now, record));
+        while let Some((time, _)) = self.ring.front() {
+            if now.duration_since(*time) > self.trigger.before {
+                if let Some((
+                    _,
+                    Record::Data(Data {
+                        limits: Some(limits),
+                        ..
+                    }),
+                )) = self.ring.pop_front()
+                {
+                    self.keep_limits(limits);
+                }
+            } else {
+                break;
+            }
+        }
+        None
+    }
+
+    // limits只在变化时记录, 丢弃的采样中的limits移到缓冲区中剩下的第一个采样上,
+    // 否则触发时写入的段中没有limits
+    fn keep_limits(&mut self, limits: Limits) {
+        let first = self.ring.iter_mut().find_map(|(_, record)| match record {
+            Record::Data(data) => Some(data),
+            _ => None,
+        });
+        if let Some(data) = first {
+            data.limits.get_or_insert(limits);
+        }
+    }
+
+    fn is_recording(&self, now: Instant) -> bool {
+        self.until.is_some_and(|until| now < until)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::tests::sample;
+
+    fn limits(nofile: u64) -> Limits {
+        Limits {
+            address_space: None,
+            data: None,
+            nofile: Some(nofile),
+            nproc: None,
+            memory_max: None,
+            cpu_max: None,
+        }
+    }
+
+    #[test]
+    fn segment_starts_with_limits() {
+        let mut buffer = TriggerBuffer::new(Trigger {
+            rss: None,
+            cpu: None,
+            cpu_for: Duration::ZERO,
+            exit: true,
+            before: Duration::from_millis(50),
+            after: Duration::from_secs(60),
+        });
+
+        let mut first = sample(Some(0));
+        first.limits = Some(limits(1024));
+        assert!(buffer.buffer(Record::Data(first)).is_none());
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(buffer.buffer(Record::Data(sample(Some(1)))).is_none());
+        assert!(buffer.buffer(Record::Data(sample(Some(2)))).is_none());
+
+        let records = buffer.fire("exited".to_owned());
+        assert!(matches!(records[0], Record::Segment(_)));
+        let data = records[1..]
+            .iter()
+            .map(|record| match record {
+                Record::Data(data) => data,
+                _ => panic!("unexpected record"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(data.len(), 2);
+        assert_eq!(data[0].time, Some(1));
+        assert_eq!(data[0].limits, Some(limits(1024)));
+        assert_eq!(data[1].limits, None);
+    }
+}